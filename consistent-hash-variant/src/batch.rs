@@ -0,0 +1,411 @@
+use crate::cluster::ClusterManager;
+use crate::error::{Error, Result};
+use consistent_hash::{Location, MigrationLimits, MigrationSchedule, Role, Transfer};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+/// What adding or removing a set of nodes in one pass moved.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchScale {
+    /// Nodes added or removed, in the order given.
+    pub nodes: Vec<String>,
+    /// `(slot_id, role, from, to)` for every replica moved, sorted; `role`
+    /// is the one the replica holds once leadership is balanced.
+    pub moved: Vec<(u64, Role, String, String)>,
+    /// Replicas the same change moves when done one node at a time, each
+    /// step balancing leadership as the batch does, or `None` if it cannot
+    /// be done that way.
+    pub stepwise: Option<usize>,
+}
+
+impl fmt::Display for BatchScale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "scale {} => moved replicas num: {}, one node at a time: ",
+            self.nodes.join(","),
+            self.moved.len()
+        )?;
+        match self.stepwise {
+            Some(stepwise) => writeln!(f, "{}", stepwise),
+            None => writeln!(f, "n/a"),
+        }
+    }
+}
+
+impl BatchScale {
+    /// Orders the moved replicas into waves within `limits`, one slot copy
+    /// each; replicas of the same slot move in separate waves.
+    pub fn schedule(&self, limits: &MigrationLimits) -> MigrationSchedule {
+        let transfers = self
+            .moved
+            .iter()
+            .map(|(slot_id, _, from, to)| Transfer {
+                id: *slot_id,
+                from: from.clone(),
+                to: to.clone(),
+                slots: 1,
+            })
+            .collect();
+        MigrationSchedule::new(transfers, limits)
+    }
+}
+
+impl ClusterManager {
+    /// Adds every node of `nodes` at its location, then moves replicas once
+    /// to the balanced layout over the final membership.
+    ///
+    /// Replicas only leave the nodes that hold more than their share, so
+    /// none moves twice, as happens when `scale_at` adds the nodes one by
+    /// one.
+    pub fn scale_out(&mut self, nodes: &[(&str, Location)]) -> Result<BatchScale> {
+        let names: Vec<&str> = nodes.iter().map(|(name, _)| *name).collect();
+        self.check_new_nodes(&names)?;
+
+        let mut stepwise = Some(0);
+        let mut cm = self.clone();
+        for (name, location) in nodes {
+            let step = cm
+                .scale_at(name, location.clone())
+                .and_then(|migrated| cm.balance_primaries().map(|_| migrated));
+            stepwise = match (stepwise, step) {
+                (Some(n), Ok(migrated)) => Some(n + migrated.len()),
+                _ => None,
+            };
+        }
+
+        let moved = self.checked(|cm| {
+            cm.add_nodes(&names);
+            for (name, location) in nodes {
+                cm.set_location(name, location.clone())?;
+            }
+            cm.rebalance_unchecked(&[])
+        })?;
+        Ok(BatchScale {
+            nodes: names.iter().map(|name| String::from(*name)).collect(),
+            moved,
+            stepwise,
+        })
+    }
+
+    /// Moves every replica off `names` in one pass, rebalancing the nodes
+    /// that stay, then removes `names`.
+    ///
+    /// Down nodes may be removed too; their replicas are recreated on the
+    /// nodes that stay.
+    pub fn scale_in(&mut self, names: &[&str]) -> Result<BatchScale> {
+        let mut seen = HashSet::new();
+        for name in names {
+            if !self.node_map.contains_key(*name) {
+                return Err(Error::NodeNotFound(String::from(*name)));
+            }
+            if !seen.insert(*name) {
+                return Err(Error::DuplicateNode(String::from(*name)));
+            }
+        }
+        let available = self.node_map.len() - names.len();
+        if available < self.replica_num as usize {
+            return Err(Error::NotEnoughNodes {
+                required: self.replica_num as usize,
+                available,
+            });
+        }
+
+        let mut stepwise = Some(0);
+        let mut cm = self.clone();
+        for name in names {
+            stepwise = match (stepwise, cm.checked(|cm| cm.scale_in_unchecked(&[name]))) {
+                (Some(n), Ok(moved)) => Some(n + moved.len()),
+                _ => None,
+            };
+        }
+
+        let moved = self.checked(|cm| cm.scale_in_unchecked(names))?;
+        Ok(BatchScale {
+            nodes: names.iter().map(|name| String::from(*name)).collect(),
+            moved,
+            stepwise,
+        })
+    }
+
+    fn scale_in_unchecked(&mut self, names: &[&str]) -> Result<Vec<(u64, Role, String, String)>> {
+        let moved = self.rebalance_unchecked(names)?;
+        for name in names {
+            self.node_map.remove(*name);
+        }
+        Ok(moved)
+    }
+
+    /// Moves replicas, role by role, from live nodes above their share and
+    /// from every node in `leaving` to live nodes below their share.
+    ///
+    /// The share of a role is its replica positions spread evenly over the
+    /// live nodes that stay; the rounding goes to the nodes already holding
+    /// the most. A leaving node's replicas that no node below its share can
+    /// take go to the least loaded node that can.
+    ///
+    /// Leadership is evened out afterwards, as by `balance_primaries`.
+    fn rebalance_unchecked(
+        &mut self,
+        leaving: &[&str],
+    ) -> Result<Vec<(u64, Role, String, String)>> {
+        let participants: Vec<String> = self
+            .node_names()
+            .into_iter()
+            .filter(|name| self.node_map[*name].is_up() || leaving.contains(name))
+            .map(String::from)
+            .collect();
+        let receivers: Vec<String> = participants
+            .iter()
+            .filter(|name| !leaving.contains(&name.as_str()))
+            .cloned()
+            .collect();
+
+        let mut moved = Vec::new();
+        for role in Role::ALL {
+            let mut held: BTreeMap<String, Vec<u64>> = participants
+                .iter()
+                .map(|name| {
+                    let mut slots: Vec<u64> = self.node_map[name]
+                        .slot_set
+                        .iter()
+                        .filter(|(_, r)| *r == role)
+                        .map(|(slot_id, _)| *slot_id)
+                        .collect();
+                    slots.sort_unstable();
+                    (name.clone(), slots)
+                })
+                .collect();
+            let total: usize = held.values().map(Vec::len).sum();
+            if total == 0 || receivers.is_empty() {
+                continue;
+            }
+
+            let mut order = receivers.clone();
+            order.sort_by_key(|name| (Reverse(held[name].len()), name.clone()));
+            let (base, extra) = (total / order.len(), total % order.len());
+            let mut target: HashMap<String, usize> = leaving
+                .iter()
+                .map(|name| (String::from(*name), 0))
+                .collect();
+            for (i, name) in order.into_iter().enumerate() {
+                target.insert(name, base + usize::from(i < extra));
+            }
+
+            loop {
+                let mut donors: Vec<&String> = held
+                    .keys()
+                    .filter(|name| held[*name].len() > target[*name])
+                    .collect();
+                donors.sort_by_key(|name| (Reverse(held[*name].len() - target[*name]), *name));
+                let mut takers: Vec<&String> = receivers
+                    .iter()
+                    .filter(|name| held[*name].len() < target[*name])
+                    .collect();
+                takers.sort_by_key(|name| (Reverse(target[*name] - held[*name].len()), *name));
+
+                let picked = donors.iter().find_map(|donor| {
+                    held[*donor].iter().find_map(|slot_id| {
+                        takers
+                            .iter()
+                            .find(|to| self.can_take(*slot_id, donor, to))
+                            .map(|to| (*slot_id, (*donor).clone(), (*to).clone()))
+                    })
+                });
+                let (slot_id, from, to) = match picked {
+                    Some(picked) => picked,
+                    None => break,
+                };
+                self.record_move(&mut held, slot_id, role, &from, &to);
+                moved.push((slot_id, role, from, to));
+            }
+
+            for from in leaving {
+                while let Some(slot_id) = held[*from].first().copied() {
+                    let to = receivers
+                        .iter()
+                        .filter(|to| self.can_take(slot_id, from, to))
+                        .min_by_key(|to| (self.node_map[*to].slot_set.len(), *to))
+                        .cloned()
+                        .ok_or(Error::PlacementUnsatisfiable(slot_id))?;
+                    self.record_move(&mut held, slot_id, role, from, &to);
+                    moved.push((slot_id, role, String::from(*from), to));
+                }
+            }
+        }
+
+        self.balance_primaries_unchecked();
+        // leader swaps may have changed the roles the replicas moved with
+        for (slot_id, role, _, to) in &mut moved {
+            if let Some(now) = self.replicaset_map[slot_id].node_map.get(to.as_str()) {
+                *role = *now;
+            }
+        }
+        moved.sort_unstable();
+        Ok(moved)
+    }
+
+    fn can_take(&self, slot_id: u64, from: &str, to: &str) -> bool {
+        !self.replicaset_map[&slot_id].node_map.contains_key(to)
+            && self.admits_move(slot_id, from, to)
+    }
+
+    fn record_move(
+        &mut self,
+        held: &mut BTreeMap<String, Vec<u64>>,
+        slot_id: u64,
+        role: Role,
+        from: &str,
+        to: &str,
+    ) {
+        self.move_replica(slot_id, role, from, to);
+        if let Some(slots) = held.get_mut(from) {
+            slots.retain(|id| *id != slot_id);
+        }
+        if let Some(slots) = held.get_mut(to) {
+            let pos = slots.binary_search(&slot_id).unwrap_or_else(|pos| pos);
+            slots.insert(pos, slot_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use consistent_hash::{DomainLevel, PlacementRule};
+
+    fn held(cm: &ClusterManager, role: Role) -> Vec<usize> {
+        cm.node_names()
+            .into_iter()
+            .map(|name| {
+                let node = cm.node(name).unwrap();
+                node.slot_set().iter().filter(|(_, r)| *r == role).count()
+            })
+            .collect()
+    }
+
+    fn assert_balanced(cm: &ClusterManager) {
+        assert!(cm.check_invariants().is_empty());
+        let primaries = held(cm, Role::Primary);
+        let spread = primaries.iter().max().unwrap() - primaries.iter().min().unwrap();
+        assert!(spread <= 1, "primaries {:?}", primaries);
+        let replicas: Vec<usize> = cm
+            .node_names()
+            .into_iter()
+            .map(|name| cm.node(name).unwrap().slot_set().len())
+            .collect();
+        let spread = replicas.iter().max().unwrap() - replicas.iter().min().unwrap();
+        assert!(spread <= 2, "replicas {:?}", replicas);
+    }
+
+    #[test]
+    fn test_scale_out() {
+        let mut cm = ClusterManager::with_replica_num(128, 3).unwrap();
+        cm.allocate(&["aaa", "bbb", "ccc", "ddd"]).unwrap();
+        let nodes: Vec<(&str, Location)> = ["eee", "fff", "ggg"]
+            .iter()
+            .map(|name| (*name, Location::default()))
+            .collect();
+        let batch = cm.scale_out(&nodes).unwrap();
+        assert_eq!(batch.nodes, vec!["eee", "fff", "ggg"]);
+        assert_balanced(&cm);
+        // every replica moves from an old node to a new one, and only once
+        assert!(batch
+            .moved
+            .iter()
+            .all(|(_, _, from, to)| from.as_str() < "eee" && to.as_str() >= "eee"));
+        let new_held: usize = ["eee", "fff", "ggg"]
+            .iter()
+            .map(|name| cm.node(name).unwrap().slot_set().len())
+            .sum();
+        assert_eq!(batch.moved.len(), new_held);
+        for (slot_id, role, _, to) in &batch.moved {
+            assert_eq!(cm.replica_set(*slot_id).unwrap().node_map()[to], *role);
+        }
+        assert!(batch.moved.len() < batch.stepwise.unwrap());
+
+        let limits = MigrationLimits::new(0, 4, 0, 2.0).unwrap();
+        let schedule = batch.schedule(&limits);
+        assert_eq!(schedule.slots, batch.moved.len());
+        for wave in &schedule.waves {
+            for name in &batch.nodes {
+                let taken = wave.transfers.iter().filter(|t| &t.to == name).count();
+                assert!(taken <= 4);
+            }
+        }
+        let most = batch
+            .nodes
+            .iter()
+            .map(|name| cm.node(name).unwrap().slot_set().len())
+            .max();
+        assert!(schedule.waves.len() >= most.unwrap().div_ceil(4));
+        assert_eq!(schedule.secs, 2.0 * schedule.waves.len() as f64);
+
+        assert_eq!(
+            cm.scale_out(&[("hhh", Location::default()), ("aaa", Location::default())]),
+            Err(Error::DuplicateNode(String::from("aaa")))
+        );
+        assert!(cm.node("hhh").is_none());
+    }
+
+    #[test]
+    fn test_scale_in() {
+        let mut cm = ClusterManager::with_replica_num(128, 3).unwrap();
+        cm.allocate(&["aaa", "bbb", "ccc", "ddd", "eee", "fff", "ggg"])
+            .unwrap();
+        cm.fail_node("ggg").unwrap();
+        let batch = cm.scale_in(&["bbb", "ggg"]).unwrap();
+        assert_eq!(cm.node_names(), vec!["aaa", "ccc", "ddd", "eee", "fff"]);
+        assert_balanced(&cm);
+        for slot_id in 0..128 {
+            let replset = cm.replica_set(slot_id).unwrap();
+            assert_eq!(replset.node_map().len(), 3);
+        }
+        assert!(batch.moved.len() <= batch.stepwise.unwrap());
+
+        assert_eq!(
+            cm.scale_in(&["zzz"]),
+            Err(Error::NodeNotFound(String::from("zzz")))
+        );
+        assert_eq!(
+            cm.scale_in(&["aaa", "ccc", "ddd"]),
+            Err(Error::NotEnoughNodes {
+                required: 3,
+                available: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_scale_in_with_rule() {
+        let mut cm = ClusterManager::with_replica_num(64, 3).unwrap();
+        cm.set_placement_rule(PlacementRule::spread(DomainLevel::Zone));
+        for (name, z) in &[
+            ("aaa", "z1"),
+            ("bbb", "z1"),
+            ("ccc", "z2"),
+            ("ddd", "z2"),
+            ("eee", "z3"),
+            ("fff", "z3"),
+        ] {
+            cm.add_node(name).unwrap();
+            let location = Location {
+                zone: Some(String::from(*z)),
+                ..Location::default()
+            };
+            cm.set_location(name, location).unwrap();
+        }
+        cm.allocate(&[]).unwrap();
+        // bbb is left alone in z1 and has to hold every slot
+        cm.set_max_load_skew(None);
+        cm.scale_in(&["aaa"]).unwrap();
+        assert!(cm.validate_placement().is_empty());
+        assert_eq!(cm.node("bbb").unwrap().slot_set().len(), 64);
+        assert!(matches!(
+            cm.scale_in(&["bbb"]),
+            Err(Error::PlacementUnsatisfiable(_))
+        ));
+        assert!(cm.node("bbb").is_some());
+    }
+}
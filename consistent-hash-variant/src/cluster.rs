@@ -0,0 +1,496 @@
+use crate::error::{Error, Result};
+use crate::invariant::DEFAULT_MAX_LOAD_SKEW;
+use crate::migration::SlotMigration;
+use consistent_hash::{Location, PlacementRule, Role};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+/// Default number of copies kept for every slot: one primary plus two
+/// secondaries.
+pub const DEFAULT_REPLICA_NUM: u64 = 3;
+
+#[derive(Debug, Clone)]
+pub struct ReplicaSet {
+    pub(crate) slot_id: u64,
+    pub(crate) node_map: HashMap<String, Role>,
+}
+
+/// Whether a node serves the replicas it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeState {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub(crate) name: String,
+    pub(crate) location: Location,
+    pub(crate) state: NodeState,
+    pub(crate) slot_set: HashSet<(u64, Role)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClusterManager {
+    pub(crate) max_slot_id: u64,
+    pub(crate) replica_num: u64,
+    pub(crate) roles: Vec<Role>,
+    pub(crate) rule: PlacementRule,
+    pub(crate) max_load_skew: Option<u32>,
+    pub(crate) replicaset_map: HashMap<u64, ReplicaSet>,
+    pub(crate) node_map: HashMap<String, Node>,
+    pub(crate) migrations: BTreeMap<u64, SlotMigration>,
+}
+
+impl ReplicaSet {
+    fn new(id: u64) -> ReplicaSet {
+        ReplicaSet {
+            slot_id: id,
+            node_map: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.node_map.clear();
+    }
+
+    pub fn slot_id(&self) -> u64 {
+        self.slot_id
+    }
+
+    pub fn node_map(&self) -> &HashMap<String, Role> {
+        &self.node_map
+    }
+
+    pub fn get_primary(&self) -> Option<&str> {
+        for (name, role) in &self.node_map {
+            if *role == Role::Primary {
+                return Some(name.as_str());
+            }
+        }
+        None
+    }
+
+    pub(crate) fn assign(&mut self, node_name: &str, role: Role) {
+        self.node_map.insert(String::from(node_name), role);
+    }
+
+    pub(crate) fn migrate(&mut self, node_name: &str) {
+        self.node_map.remove(node_name);
+    }
+}
+
+impl Node {
+    fn new(name: &str) -> Node {
+        Node {
+            name: String::from(name),
+            location: Location::default(),
+            state: NodeState::Up,
+            slot_set: HashSet::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn location(&self) -> &Location {
+        &self.location
+    }
+
+    pub fn state(&self) -> NodeState {
+        self.state
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.state == NodeState::Up
+    }
+
+    pub fn slot_set(&self) -> &HashSet<(u64, Role)> {
+        &self.slot_set
+    }
+
+    pub(crate) fn pickup_slot(&mut self, slot_id: u64, role: Role) {
+        self.slot_set.insert((slot_id, role));
+    }
+}
+
+impl ClusterManager {
+    pub fn new(slot_num: u64) -> Result<ClusterManager> {
+        Self::with_replica_num(slot_num, DEFAULT_REPLICA_NUM)
+    }
+
+    /// Creates a cluster keeping `replica_num` copies of every slot, primary
+    /// included.
+    pub fn with_replica_num(slot_num: u64, replica_num: u64) -> Result<ClusterManager> {
+        if replica_num == 0 {
+            return Err(Error::InvalidReplicaNum(replica_num));
+        }
+        Self::with_roles(slot_num, &Role::layout(replica_num))
+    }
+
+    /// Creates a cluster keeping one copy of every slot per entry of
+    /// `roles`, the primary first.
+    pub fn with_roles(slot_num: u64, roles: &[Role]) -> Result<ClusterManager> {
+        if slot_num == 0 {
+            return Err(Error::InvalidSlotNum(slot_num));
+        }
+        Role::check_layout(roles).map_err(Error::InvalidRoles)?;
+        let mut cm = ClusterManager {
+            max_slot_id: slot_num,
+            replica_num: roles.len() as u64,
+            roles: roles.to_vec(),
+            rule: PlacementRule::default(),
+            max_load_skew: Some(DEFAULT_MAX_LOAD_SKEW),
+            replicaset_map: HashMap::new(),
+            node_map: HashMap::new(),
+            migrations: BTreeMap::new(),
+        };
+
+        for i in 0..cm.max_slot_id {
+            cm.replicaset_map.insert(i, ReplicaSet::new(i));
+        }
+        Ok(cm)
+    }
+
+    pub fn slot_num(&self) -> u64 {
+        self.max_slot_id
+    }
+
+    pub fn replica_num(&self) -> u64 {
+        self.replica_num
+    }
+
+    /// Returns the role of every replica position, primary first.
+    pub fn roles(&self) -> &[Role] {
+        &self.roles
+    }
+
+    pub fn node(&self, name: &str) -> Option<&Node> {
+        self.node_map.get(name)
+    }
+
+    /// Returns the node names sorted, for stable output.
+    pub fn node_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.node_map.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn replica_set(&self, slot_id: u64) -> Option<&ReplicaSet> {
+        self.replicaset_map.get(&slot_id)
+    }
+
+    pub(crate) fn check_new_nodes(&self, names: &[&str]) -> Result<()> {
+        let mut seen = HashSet::new();
+        for name in names {
+            if self.node_map.contains_key(*name) || !seen.insert(*name) {
+                return Err(Error::DuplicateNode(String::from(*name)));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn add_nodes(&mut self, names: &[&str]) {
+        for name in names {
+            self.node_map.insert(String::from(*name), Node::new(name));
+        }
+    }
+
+    /// Adds the node `name` without slots; `allocate` hands it some.
+    pub fn add_node(&mut self, name: &str) -> Result<()> {
+        self.check_new_nodes(&[name])?;
+        self.add_nodes(&[name]);
+        Ok(())
+    }
+
+    /// Adds `names` and assigns every slot's replicas round-robin, the first
+    /// node of each replica set being the primary.
+    ///
+    /// Nodes the placement rule does not admit next to the replicas chosen
+    /// so far are skipped for that slot.
+    pub fn allocate(&mut self, names: &[&str]) -> Result<()> {
+        self.check_new_nodes(names)?;
+        let available = self.node_map.len() + names.len();
+        if available < self.replica_num as usize {
+            return Err(Error::NotEnoughNodes {
+                required: self.replica_num as usize,
+                available,
+            });
+        }
+        self.checked(|cm| cm.allocate_unchecked(names))
+    }
+
+    fn allocate_unchecked(&mut self, names: &[&str]) -> Result<()> {
+        self.add_nodes(names);
+        let assignment = self.plan_allocation()?;
+        for replset in self.replicaset_map.values_mut() {
+            replset.reset();
+        }
+        for node in self.node_map.values_mut() {
+            node.slot_set.clear();
+        }
+
+        for (i, replicas) in assignment.iter().enumerate() {
+            let i = i as u64;
+            for (r, name) in replicas.iter().enumerate() {
+                if let Some(node) = self.node_map.get_mut(name) {
+                    node.pickup_slot(i, self.roles[r]);
+                    if let Some(replset) = self.replicaset_map.get_mut(&i) {
+                        replset.assign(name.as_str(), self.roles[r]);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Orders the nodes so that consecutive ones sit in different domains
+    /// at the coarsest level the rule constrains, taking one node from each
+    /// domain in turn. Under the default rule this is plain name order.
+    fn interleaved_node_names(&self) -> Vec<&str> {
+        let level = self.rule.constraints.iter().map(|c| c.level).max();
+        let mut domains: BTreeMap<&str, VecDeque<&str>> = BTreeMap::new();
+        for name in self.node_names() {
+            let domain = match level {
+                Some(level) => self.domain_of(name, level),
+                None => name,
+            };
+            domains.entry(domain).or_default().push_back(name);
+        }
+        let mut ns = Vec::with_capacity(self.node_map.len());
+        while ns.len() < self.node_map.len() {
+            for names in domains.values_mut() {
+                ns.extend(names.pop_front());
+            }
+        }
+        ns
+    }
+
+    /// Picks the replicas of every slot, primary first, without touching
+    /// the current assignment.
+    fn plan_allocation(&self) -> Result<Vec<Vec<String>>> {
+        let mut ns = self.interleaved_node_names();
+        let mut assignment = Vec::with_capacity(self.max_slot_id as usize);
+
+        for i in 0..self.max_slot_id {
+            let mut chosen: Vec<(&str, &Location)> = Vec::new();
+            for name in &ns {
+                if chosen.len() == self.replica_num as usize {
+                    break;
+                }
+                let location = &self.node_map[*name].location;
+                if self.rule.admits(name, location, &chosen) {
+                    chosen.push((name, location));
+                }
+            }
+            if chosen.len() < self.replica_num as usize {
+                return Err(Error::PlacementUnsatisfiable(i));
+            }
+            assignment.push(chosen.iter().map(|(name, _)| String::from(*name)).collect());
+            ns.rotate_left(1);
+        }
+        Ok(assignment)
+    }
+
+    /// Adds the node `name` and migrates slot replicas of every role to it.
+    ///
+    /// Returns the migrated `(slot_id, role)` pairs, sorted.
+    pub fn scale(&mut self, name: &str) -> Result<Vec<(u64, Role)>> {
+        self.scale_at(name, Location::default())
+    }
+
+    /// Like `scale`, placing the new node at `location` first.
+    ///
+    /// For every role the new node takes `slot_num * positions / nodes`
+    /// replicas, the whole-number share of the replica positions with that
+    /// role over the live nodes, one at a time from whichever live node
+    /// holds the most of them.
+    /// A replica only moves if the placement rule admits the new node next
+    /// to the rest of its replica set, and at most one replica of a slot
+    /// moves, so the new node never holds two roles of the same slot.
+    pub fn scale_at(&mut self, name: &str, location: Location) -> Result<Vec<(u64, Role)>> {
+        self.check_new_nodes(&[name])?;
+        if self.node_map.len() < self.replica_num as usize {
+            return Err(Error::NotEnoughNodes {
+                required: self.replica_num as usize,
+                available: self.node_map.len(),
+            });
+        }
+        self.checked(|cm| cm.scale_unchecked(name, location))
+    }
+
+    fn scale_unchecked(&mut self, name: &str, location: Location) -> Result<Vec<(u64, Role)>> {
+        self.add_nodes(&[name]);
+        if let Some(node) = self.node_map.get_mut(name) {
+            node.location = location;
+        }
+        // down nodes neither share the load nor hand out replicas
+        let nodes_num = self.node_map.values().filter(|node| node.is_up()).count() as u64;
+
+        let mut migrated_slots: HashSet<u64> = HashSet::new();
+        let mut migrated = Vec::new();
+        for role in Role::ALL {
+            let positions = self.roles.iter().filter(|r| **r == role).count() as u64;
+            let share = self.max_slot_id * positions / nodes_num;
+            let mut donors: Vec<(String, Vec<u64>)> = self
+                .node_names()
+                .into_iter()
+                .filter(|donor| *donor != name && self.node_map[*donor].is_up())
+                .map(|donor| {
+                    let mut slots: Vec<u64> = self.node_map[donor]
+                        .slot_set
+                        .iter()
+                        .filter(|(_, r)| *r == role)
+                        .map(|(slot_id, _)| *slot_id)
+                        .collect();
+                    slots.sort_unstable();
+                    (String::from(donor), slots)
+                })
+                .collect();
+
+            for _ in 0..share {
+                donors.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
+                let picked = donors.iter().enumerate().find_map(|(d, (donor, slots))| {
+                    slots
+                        .iter()
+                        .position(|slot_id| {
+                            !migrated_slots.contains(slot_id)
+                                && self.admits_move(*slot_id, donor, name)
+                        })
+                        .map(|i| (d, i))
+                });
+                let (d, i) = match picked {
+                    Some(picked) => picked,
+                    None => break,
+                };
+                let slot_id = donors[d].1.remove(i);
+                self.move_replica(slot_id, role, &donors[d].0, name);
+                migrated_slots.insert(slot_id);
+                migrated.push((slot_id, role));
+            }
+        }
+
+        migrated.sort_unstable();
+        Ok(migrated)
+    }
+
+    /// Checks whether `to` may take over the replica `from` holds of
+    /// `slot_id` without breaking the placement rule; a migrating slot stays
+    /// put.
+    pub(crate) fn admits_move(&self, slot_id: u64, from: &str, to: &str) -> bool {
+        if self.migrations.contains_key(&slot_id) {
+            return false;
+        }
+        let others: Vec<(&str, &Location)> = self.replicaset_map[&slot_id]
+            .node_map
+            .keys()
+            .filter(|other| *other != from)
+            .map(|other| (other.as_str(), &self.node_map[other].location))
+            .collect();
+        self.rule.admits(to, &self.node_map[to].location, &others)
+    }
+
+    pub(crate) fn move_replica(&mut self, slot_id: u64, role: Role, from: &str, to: &str) {
+        if let Some(replset) = self.replicaset_map.get_mut(&slot_id) {
+            replset.migrate(from);
+            replset.assign(to, role);
+        }
+        if let Some(node) = self.node_map.get_mut(from) {
+            node.slot_set.remove(&(slot_id, role));
+        }
+        if let Some(node) = self.node_map.get_mut(to) {
+            node.pickup_slot(slot_id, role);
+        }
+    }
+
+    pub fn show_nodes(&self) {
+        let mut total_primary_slots = 0;
+        let mut total_secondary_slots = 0;
+        let mut total_non_voting_slots = 0;
+        for (i, name) in self.node_names().into_iter().enumerate() {
+            let node = &self.node_map[name];
+            let state = if node.is_up() { "" } else { " (down)" };
+            println!("============= {} Node name: {}{}", i, node.name, state);
+            let mut primary_slots = 0;
+            let mut secondary_slots = 0;
+            let mut non_voting_slots = 0;
+            for (_, role) in &node.slot_set {
+                match role {
+                    Role::Primary => primary_slots += 1,
+                    Role::Secondary => secondary_slots += 1,
+                    _ => non_voting_slots += 1,
+                }
+            }
+            println!(
+                "primary slots count: {}, secondary slots count: {}, non-voting slots count: {}",
+                primary_slots, secondary_slots, non_voting_slots,
+            );
+            total_primary_slots += primary_slots;
+            total_secondary_slots += secondary_slots;
+            total_non_voting_slots += non_voting_slots;
+        }
+        println!(
+            "total primary slots count: {}, total secondary slots count: {}, \
+             total non-voting slots count: {}, replica num: {}",
+            total_primary_slots, total_secondary_slots, total_non_voting_slots, self.replica_num
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replica_count(cm: &ClusterManager) -> usize {
+        cm.node_map.values().map(|n| n.slot_set().len()).sum()
+    }
+
+    #[test]
+    fn test_new_errors() {
+        assert_eq!(
+            ClusterManager::new(0).unwrap_err(),
+            Error::InvalidSlotNum(0)
+        );
+        assert_eq!(
+            ClusterManager::with_replica_num(128, 0).unwrap_err(),
+            Error::InvalidReplicaNum(0)
+        );
+        let mut cm = ClusterManager::with_replica_num(128, 4).unwrap();
+        assert_eq!(
+            cm.allocate(&["aaa", "bbb", "ccc"]),
+            Err(Error::NotEnoughNodes {
+                required: 4,
+                available: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_allocate() {
+        for replica_num in 1..=5 {
+            let mut cm = ClusterManager::with_replica_num(128, replica_num).unwrap();
+            cm.allocate(&["aaa", "bbb", "ccc", "ddd", "eee", "fff"])
+                .unwrap();
+            assert_eq!(replica_count(&cm), 128 * replica_num as usize);
+            for slot_id in 0..128 {
+                let replset = cm.replica_set(slot_id).unwrap();
+                assert_eq!(replset.node_map().len(), replica_num as usize);
+                assert!(replset.get_primary().is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn test_scale() {
+        let mut cm = ClusterManager::with_replica_num(128, 2).unwrap();
+        cm.allocate(&["aaa", "bbb", "ccc", "ddd"]).unwrap();
+        let migrated = cm.scale("eee").unwrap();
+        assert!(!migrated.is_empty());
+        assert!(migrated.iter().all(|(_, role)| role.is_voter()));
+        assert_eq!(cm.node("eee").unwrap().slot_set().len(), migrated.len());
+        assert_eq!(
+            cm.scale("eee"),
+            Err(Error::DuplicateNode(String::from("eee")))
+        );
+    }
+}
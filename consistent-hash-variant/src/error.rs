@@ -0,0 +1,83 @@
+use crate::invariant::Violation;
+use std::error;
+use std::fmt;
+
+/// Errors returned by `ClusterManager` operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The cluster was created with zero slots.
+    InvalidSlotNum(u64),
+    /// The replication factor must be at least one.
+    InvalidReplicaNum(u64),
+    /// A replica role layout needs exactly one primary, in first position.
+    InvalidRoles(String),
+    /// The operation needs more nodes than the cluster has.
+    NotEnoughNodes { required: usize, available: usize },
+    /// A node with this name is already part of the cluster.
+    DuplicateNode(String),
+    /// No node with this name is part of the cluster.
+    NodeNotFound(String),
+    /// No choice of nodes gives this slot its replicas within the placement
+    /// rule.
+    PlacementUnsatisfiable(u64),
+    /// The operation would have left the cluster breaking an invariant, so
+    /// it was not applied.
+    InvariantViolated(Violation),
+    /// The slot already has a migration in progress.
+    MigrationInProgress(u64),
+    /// The slot has no migration in progress.
+    NoMigration(u64),
+    /// The migration still has keys to move before it can finish.
+    MigrationIncomplete { slot_id: u64, keys_left: u64 },
+    /// The replica cannot migrate between these nodes.
+    InvalidMigration(String),
+    /// A topology snapshot could not be read, written or decoded, or it
+    /// describes an inconsistent cluster.
+    InvalidSnapshot(String),
+    /// The snapshot was written with a format version this build cannot read.
+    UnsupportedSnapshotVersion(u32),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidSlotNum(n) => write!(f, "invalid slot number: {}", n),
+            Error::InvalidReplicaNum(n) => write!(f, "invalid replica number: {}", n),
+            Error::InvalidRoles(msg) => write!(f, "invalid roles: {}", msg),
+            Error::NotEnoughNodes {
+                required,
+                available,
+            } => write!(
+                f,
+                "not enough nodes: required {}, available {}",
+                required, available
+            ),
+            Error::DuplicateNode(name) => write!(f, "node {} already exists", name),
+            Error::NodeNotFound(name) => write!(f, "node {} not found", name),
+            Error::PlacementUnsatisfiable(slot_id) => {
+                write!(
+                    f,
+                    "slot {} cannot be placed within the placement rule",
+                    slot_id
+                )
+            }
+            Error::InvariantViolated(v) => write!(f, "invariant violated: {}", v),
+            Error::MigrationInProgress(slot_id) => {
+                write!(f, "slot {} is already migrating", slot_id)
+            }
+            Error::NoMigration(slot_id) => write!(f, "slot {} is not migrating", slot_id),
+            Error::MigrationIncomplete { slot_id, keys_left } => {
+                write!(f, "slot {} has {} keys left to migrate", slot_id, keys_left)
+            }
+            Error::InvalidMigration(msg) => write!(f, "invalid migration: {}", msg),
+            Error::InvalidSnapshot(msg) => write!(f, "invalid snapshot: {}", msg),
+            Error::UnsupportedSnapshotVersion(v) => {
+                write!(f, "unsupported snapshot version: {}", v)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
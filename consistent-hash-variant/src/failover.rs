@@ -0,0 +1,407 @@
+use crate::cluster::{ClusterManager, Node, NodeState};
+use crate::error::{Error, Result};
+use crate::invariant::Violation;
+use crate::migration::SlotMigration;
+use consistent_hash::{Location, Role};
+use std::collections::HashMap;
+use std::fmt;
+
+/// What taking one node down did to the slots it held.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Failover {
+    pub node: String,
+    /// `(slot_id, new primary)` for every slot the node led, sorted.
+    pub promoted: Vec<(u64, String)>,
+    /// Slots with no live replica left to serve them, sorted.
+    pub unavailable: Vec<u64>,
+    /// Migrations from or to the node, given up on, sorted by slot id.
+    pub aborted: Vec<SlotMigration>,
+    /// Invariants the cluster breaks once the node is down; the failure
+    /// applies regardless.
+    pub violations: Vec<Violation>,
+}
+
+impl fmt::Display for Failover {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "fail {} => promoted primaries: {}, unavailable slots: {}, aborted migrations: {}, violations: {}",
+            self.node,
+            self.promoted.len(),
+            self.unavailable.len(),
+            self.aborted.len(),
+            self.violations.len()
+        )
+    }
+}
+
+/// What `re_replicate` rebuilt, and what it could not.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReReplication {
+    /// `(slot_id, role, source, target)` for every replica rebuilt on
+    /// `target` by copying the live replica on `source`, sorted.
+    pub rebuilt: Vec<(u64, Role, String, String)>,
+    /// Slots whose replicas are all down, so there is nothing to copy from;
+    /// they stay where they are, sorted.
+    pub lost: Vec<u64>,
+}
+
+impl fmt::Display for ReReplication {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "re-replicate => rebuilt replicas num: {}, lost slots: {}",
+            self.rebuilt.len(),
+            self.lost.len()
+        )
+    }
+}
+
+impl ClusterManager {
+    fn node_mut_or_err(&mut self, name: &str) -> Result<&mut Node> {
+        self.node_map
+            .get_mut(name)
+            .ok_or_else(|| Error::NodeNotFound(String::from(name)))
+    }
+
+    /// Names of the nodes that are down, sorted.
+    pub fn down_nodes(&self) -> Vec<&str> {
+        self.node_names()
+            .into_iter()
+            .filter(|name| !self.node_map[*name].is_up())
+            .collect()
+    }
+
+    /// Whether the primary of `slot_id` is up to serve it.
+    pub fn slot_available(&self, slot_id: u64) -> bool {
+        self.replicaset_map
+            .get(&slot_id)
+            .and_then(|replset| replset.get_primary())
+            .is_some_and(|primary| self.node_map[primary].is_up())
+    }
+
+    /// Marks `name` down and, in every replica set it leads, promotes a
+    /// live secondary to primary; learners, witnesses and arbiters never
+    /// take over.
+    ///
+    /// The two replicas swap roles, so the failed node keeps holding its
+    /// slots as a secondary until `re_replicate` moves them away. Migrations
+    /// from or to the node are aborted, so the new primaries route their
+    /// slots without ASK redirects.
+    ///
+    /// A failure is never refused; the invariants it breaks are listed in
+    /// the report instead.
+    pub fn fail_node(&mut self, name: &str) -> Result<Failover> {
+        self.node_mut_or_err(name)?.state = NodeState::Down;
+
+        let mut led: Vec<u64> = self.node_map[name]
+            .slot_set
+            .iter()
+            .filter(|(_, role)| *role == Role::Primary)
+            .map(|(slot_id, _)| *slot_id)
+            .collect();
+        led.sort_unstable();
+
+        let mut failover = Failover {
+            node: String::from(name),
+            ..Failover::default()
+        };
+        let stalled: Vec<u64> = self
+            .migrations
+            .values()
+            .filter(|m| m.from == name || m.to == name)
+            .map(|m| m.slot_id)
+            .collect();
+        for slot_id in stalled {
+            failover.aborted.extend(self.migrations.remove(&slot_id));
+        }
+        for slot_id in led {
+            let replset = &self.replicaset_map[&slot_id];
+            let successor = replset
+                .node_map
+                .iter()
+                .filter(|(other, role)| role.can_promote() && self.node_map[*other].is_up())
+                .min_by_key(|(other, _)| other.as_str())
+                .map(|(other, role)| (other.clone(), *role));
+            let (successor, role) = match successor {
+                Some(successor) => successor,
+                None => {
+                    failover.unavailable.push(slot_id);
+                    continue;
+                }
+            };
+            self.set_role(slot_id, name, role);
+            self.set_role(slot_id, &successor, Role::Primary);
+            failover.promoted.push((slot_id, successor));
+        }
+        failover.violations = self.check_invariants();
+        Ok(failover)
+    }
+
+    /// Marks `name` up again; it serves whatever it still holds. Like a
+    /// failure, a recovery is never refused.
+    pub fn recover_node(&mut self, name: &str) -> Result<()> {
+        self.node_mut_or_err(name)?.state = NodeState::Up;
+        Ok(())
+    }
+
+    /// Rebuilds every replica held by a down node on a live node outside the
+    /// replica set, keeping its role, so that each slot is back to
+    /// `replica_num` live copies.
+    ///
+    /// Each rebuilt replica is copied from a live replica of its slot, the
+    /// primary if it is up; slots with no live replica are reported lost.
+    /// The target is the least loaded live node the placement rule admits.
+    /// Nothing moves unless every replica finds one.
+    pub fn re_replicate(&mut self) -> Result<ReReplication> {
+        self.checked(|cm| cm.re_replicate_unchecked())
+    }
+
+    /// The live replica of `slot_id` to copy from, the primary if it is up.
+    fn live_source(&self, slot_id: u64) -> Option<&str> {
+        self.replicaset_map[&slot_id]
+            .node_map
+            .iter()
+            .filter(|(name, _)| self.node_map[*name].is_up())
+            .min_by_key(|(name, role)| (**role, name.as_str()))
+            .map(|(name, _)| name.as_str())
+    }
+
+    fn re_replicate_unchecked(&mut self) -> Result<ReReplication> {
+        let mut load: HashMap<&str, usize> = self
+            .node_map
+            .values()
+            .filter(|node| node.is_up())
+            .map(|node| (node.name.as_str(), node.slot_set.len()))
+            .collect();
+        let mut lost: Vec<(u64, Role, &str)> = self
+            .node_map
+            .values()
+            .filter(|node| !node.is_up())
+            .flat_map(|node| {
+                node.slot_set
+                    .iter()
+                    .map(move |(slot_id, role)| (*slot_id, *role, node.name.as_str()))
+            })
+            .collect();
+        lost.sort_unstable();
+
+        let mut report = ReReplication::default();
+        let mut plan: Vec<(u64, Role, String, String, String)> = Vec::new();
+        let mut members: HashMap<u64, Vec<&str>> = HashMap::new();
+        for (slot_id, role, from) in lost {
+            let source = match self.live_source(slot_id) {
+                Some(source) => source,
+                None => {
+                    if report.lost.last() != Some(&slot_id) {
+                        report.lost.push(slot_id);
+                    }
+                    continue;
+                }
+            };
+            let members = members.entry(slot_id).or_insert_with(|| {
+                self.replicaset_map[&slot_id]
+                    .node_map
+                    .keys()
+                    .map(String::as_str)
+                    .collect()
+            });
+            members.retain(|member| *member != from);
+            let others: Vec<(&str, &Location)> = members
+                .iter()
+                .map(|member| (*member, &self.node_map[*member].location))
+                .collect();
+            let target = self
+                .node_names()
+                .into_iter()
+                .filter(|name| self.node_map[*name].is_up() && !members.contains(name))
+                .filter(|name| {
+                    self.rule
+                        .admits(name, &self.node_map[*name].location, &others)
+                })
+                .min_by_key(|name| (load[*name], *name))
+                .ok_or(Error::PlacementUnsatisfiable(slot_id))?;
+            *load.get_mut(target).unwrap() += 1;
+            members.push(target);
+            plan.push((
+                slot_id,
+                role,
+                String::from(from),
+                String::from(source),
+                String::from(target),
+            ));
+        }
+
+        for (slot_id, role, from, source, to) in plan {
+            self.move_replica(slot_id, role, &from, &to);
+            report.rebuilt.push((slot_id, role, source, to));
+        }
+        Ok(report)
+    }
+
+    pub(crate) fn set_role(&mut self, slot_id: u64, name: &str, role: Role) {
+        if let Some(replset) = self.replicaset_map.get_mut(&slot_id) {
+            if let Some(old) = replset.node_map.insert(String::from(name), role) {
+                if let Some(node) = self.node_map.get_mut(name) {
+                    node.slot_set.remove(&(slot_id, old));
+                    node.pickup_slot(slot_id, role);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_cluster() -> ClusterManager {
+        let mut cm = ClusterManager::with_replica_num(64, 3).unwrap();
+        cm.allocate(&["aaa", "bbb", "ccc", "ddd", "eee"]).unwrap();
+        cm
+    }
+
+    #[test]
+    fn test_fail_node() {
+        let mut cm = new_cluster();
+        let led = cm
+            .node("aaa")
+            .unwrap()
+            .slot_set()
+            .iter()
+            .filter(|(_, role)| *role == Role::Primary)
+            .count();
+        let failover = cm.fail_node("aaa").unwrap();
+        assert_eq!(failover.promoted.len(), led);
+        assert!(failover.unavailable.is_empty());
+        assert_eq!(cm.down_nodes(), vec!["aaa"]);
+        for (slot_id, primary) in &failover.promoted {
+            assert_eq!(
+                cm.replica_set(*slot_id).unwrap().get_primary(),
+                Some(primary.as_str())
+            );
+        }
+        assert!((0..64).all(|slot_id| cm.slot_available(slot_id)));
+        assert_eq!(
+            cm.fail_node("zzz"),
+            Err(Error::NodeNotFound(String::from("zzz")))
+        );
+    }
+
+    #[test]
+    fn test_scale_after_failure() {
+        let mut cm = new_cluster();
+        cm.set_max_load_skew(None);
+        cm.fail_node("aaa").unwrap();
+        let held = cm.node("aaa").unwrap().slot_set().clone();
+        let migrated = cm.scale("fff").unwrap();
+        assert_eq!(cm.node("aaa").unwrap().slot_set(), &held);
+        // the share of 64 primaries and 128 secondaries over 5 live nodes
+        assert_eq!(migrated.len(), 64 / 5 + 128 / 5);
+        assert_eq!(cm.node("fff").unwrap().slot_set().len(), migrated.len());
+    }
+
+    #[test]
+    fn test_re_replicate() {
+        let mut cm = new_cluster();
+        let held = cm.node("bbb").unwrap().slot_set().len();
+        cm.fail_node("bbb").unwrap();
+        let report = cm.re_replicate().unwrap();
+        assert_eq!(report.rebuilt.len(), held);
+        assert!(report.lost.is_empty());
+        for (slot_id, _, source, _) in &report.rebuilt {
+            assert_ne!(source, "bbb");
+            assert!(cm
+                .replica_set(*slot_id)
+                .unwrap()
+                .node_map()
+                .contains_key(source));
+        }
+        assert!(cm.node("bbb").unwrap().slot_set().is_empty());
+        for slot_id in 0..64 {
+            let replset = cm.replica_set(slot_id).unwrap();
+            let mut roles: Vec<Role> = replset.node_map().values().copied().collect();
+            roles.sort_unstable();
+            assert_eq!(roles, cm.roles());
+            assert!(replset.node_map().keys().all(|name| name != "bbb"));
+        }
+        assert!(cm.validate_placement().is_empty());
+        cm.recover_node("bbb").unwrap();
+        assert!(cm.down_nodes().is_empty());
+    }
+
+    #[test]
+    fn test_non_voting_roles() {
+        let roles = [Role::Primary, Role::Learner, Role::Secondary];
+        let mut cm = ClusterManager::with_roles(32, &roles).unwrap();
+        cm.allocate(&["aaa", "bbb", "ccc", "ddd"]).unwrap();
+        let failover = cm.fail_node("aaa").unwrap();
+        assert!(failover.unavailable.is_empty());
+        for (slot_id, primary) in &failover.promoted {
+            let replset = cm.replica_set(*slot_id).unwrap();
+            assert_eq!(replset.node_map()["aaa"], Role::Secondary);
+            assert_eq!(replset.node_map()[primary], Role::Primary);
+        }
+
+        let mut cm = ClusterManager::with_roles(8, &[Role::Primary, Role::Learner]).unwrap();
+        cm.allocate(&["aaa", "bbb"]).unwrap();
+        let failover = cm.fail_node("aaa").unwrap();
+        assert!(failover.promoted.is_empty());
+        assert_eq!(failover.unavailable.len(), 4);
+    }
+
+    #[test]
+    fn test_unavailable() {
+        let mut cm = ClusterManager::with_replica_num(8, 2).unwrap();
+        cm.allocate(&["aaa", "bbb", "ccc"]).unwrap();
+        cm.fail_node("aaa").unwrap();
+        let failover = cm.fail_node("bbb").unwrap();
+        assert!(!failover.unavailable.is_empty());
+        for slot_id in &failover.unavailable {
+            assert!(!cm.slot_available(*slot_id));
+        }
+        assert!(cm.re_replicate().is_err());
+    }
+
+    #[test]
+    fn test_lost_slots() {
+        let mut cm = ClusterManager::with_replica_num(16, 2).unwrap();
+        cm.allocate(&["aaa", "bbb", "ccc", "ddd", "eee", "fff"])
+            .unwrap();
+        cm.set_max_load_skew(None);
+        let members = |cm: &ClusterManager, slot_id| -> Vec<String> {
+            let mut names: Vec<String> = cm
+                .replica_set(slot_id)
+                .unwrap()
+                .node_map()
+                .keys()
+                .cloned()
+                .collect();
+            names.sort_unstable();
+            names
+        };
+        let dead = members(&cm, 0);
+        for name in &dead {
+            cm.fail_node(name).unwrap();
+        }
+        let lost: Vec<u64> = (0..16)
+            .filter(|slot_id| members(&cm, *slot_id) == dead)
+            .collect();
+        assert!(lost.contains(&0));
+
+        let report = cm.re_replicate().unwrap();
+        assert_eq!(report.lost, lost);
+        assert_eq!(members(&cm, 0), dead);
+        assert!(!cm.slot_available(0));
+        for (slot_id, _, source, target) in &report.rebuilt {
+            assert!(!lost.contains(slot_id));
+            assert!(cm.node(source).unwrap().is_up());
+            assert!(cm.node(target).unwrap().is_up());
+        }
+        for slot_id in (0..16).filter(|slot_id| !lost.contains(slot_id)) {
+            assert!(members(&cm, slot_id)
+                .iter()
+                .all(|name| !dead.contains(name)));
+        }
+    }
+}
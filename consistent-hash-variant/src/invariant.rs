@@ -0,0 +1,369 @@
+use crate::cluster::ClusterManager;
+use crate::error::{Error, Result};
+use consistent_hash::Role;
+use std::fmt;
+
+/// Default bound on how far a live node's replica count may exceed the
+/// average, in percent of the average.
+pub const DEFAULT_MAX_LOAD_SKEW: u32 = 50;
+
+/// A broken promise of the replica layout, found by `check_invariants`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// The replica set does not have exactly one primary.
+    PrimaryCount { slot_id: u64, primaries: usize },
+    /// The replica set does not have `replica_num` replicas.
+    ReplicaCount {
+        slot_id: u64,
+        expected: usize,
+        actual: usize,
+    },
+    /// The roles in the replica set differ from the cluster's layout.
+    RoleLayout { slot_id: u64, roles: Vec<Role> },
+    /// The replica set names a node that is not in the cluster.
+    UnknownNode { slot_id: u64, node: String },
+    /// The node's slot set and the replica set disagree about a replica.
+    SlotSetMismatch {
+        node: String,
+        slot_id: u64,
+        role: Role,
+    },
+    /// The migration's source left the replica set or its target joined it
+    /// without the migration finishing.
+    StaleMigration {
+        slot_id: u64,
+        from: String,
+        to: String,
+    },
+    /// The live node holds more replicas than the skew allows.
+    Overloaded {
+        node: String,
+        replicas: usize,
+        max: usize,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::PrimaryCount { slot_id, primaries } => {
+                write!(f, "slot {} has {} primaries", slot_id, primaries)
+            }
+            Violation::ReplicaCount {
+                slot_id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "slot {} has {} replicas, expected {}",
+                slot_id, actual, expected
+            ),
+            Violation::RoleLayout { slot_id, roles } => {
+                write!(f, "slot {} has roles {:?}", slot_id, roles)
+            }
+            Violation::UnknownNode { slot_id, node } => {
+                write!(f, "slot {} names unknown node {}", slot_id, node)
+            }
+            Violation::SlotSetMismatch {
+                node,
+                slot_id,
+                role,
+            } => write!(
+                f,
+                "node {} and slot {} disagree on a {} replica",
+                node, slot_id, role
+            ),
+            Violation::StaleMigration { slot_id, from, to } => write!(
+                f,
+                "slot {} no longer migrates from {} to {}",
+                slot_id, from, to
+            ),
+            Violation::Overloaded {
+                node,
+                replicas,
+                max,
+            } => write!(
+                f,
+                "node {} holds {} replicas, at most {} allowed",
+                node, replicas, max
+            ),
+        }
+    }
+}
+
+impl ClusterManager {
+    pub fn max_load_skew(&self) -> Option<u32> {
+        self.max_load_skew
+    }
+
+    /// Bounds every live node's replica count to the average plus `skew`
+    /// percent of it; `None` drops the bound.
+    ///
+    /// Nodes below the average are fine: a node just added or recovered
+    /// holds nothing until replicas move to it. Placement rules over
+    /// unevenly sized domains can force a wider skew.
+    pub fn set_max_load_skew(&mut self, skew: Option<u32>) {
+        self.max_load_skew = skew;
+    }
+
+    /// Lists every way the cluster breaks its replica layout.
+    ///
+    /// Replica set checks are skipped until `allocate` has placed any
+    /// replica; down nodes are left out of the load bound.
+    pub fn check_invariants(&self) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let allocated = self
+            .replicaset_map
+            .values()
+            .any(|replset| !replset.node_map.is_empty());
+
+        let mut layout = self.roles.clone();
+        layout.sort_unstable();
+        for slot_id in 0..self.max_slot_id {
+            let replset = &self.replicaset_map[&slot_id];
+            for (name, role) in &replset.node_map {
+                match self.node_map.get(name) {
+                    None => violations.push(Violation::UnknownNode {
+                        slot_id,
+                        node: name.clone(),
+                    }),
+                    Some(node) if !node.slot_set.contains(&(slot_id, *role)) => {
+                        violations.push(Violation::SlotSetMismatch {
+                            node: name.clone(),
+                            slot_id,
+                            role: *role,
+                        })
+                    }
+                    Some(_) => {}
+                }
+            }
+            if !allocated {
+                continue;
+            }
+            let mut roles: Vec<Role> = replset.node_map.values().copied().collect();
+            roles.sort_unstable();
+            let primaries = roles.iter().filter(|r| **r == Role::Primary).count();
+            if primaries != 1 {
+                violations.push(Violation::PrimaryCount { slot_id, primaries });
+            } else if roles.len() != layout.len() {
+                violations.push(Violation::ReplicaCount {
+                    slot_id,
+                    expected: layout.len(),
+                    actual: roles.len(),
+                });
+            } else if roles != layout {
+                violations.push(Violation::RoleLayout { slot_id, roles });
+            }
+        }
+
+        for name in self.node_names() {
+            let node = &self.node_map[name];
+            let mut slots: Vec<&(u64, Role)> = node.slot_set.iter().collect();
+            slots.sort_unstable();
+            for (slot_id, role) in slots {
+                let held = self
+                    .replicaset_map
+                    .get(slot_id)
+                    .and_then(|replset| replset.node_map.get(name));
+                if held != Some(role) {
+                    violations.push(Violation::SlotSetMismatch {
+                        node: String::from(name),
+                        slot_id: *slot_id,
+                        role: *role,
+                    });
+                }
+            }
+        }
+
+        for migration in self.migrations.values() {
+            let members = &self.replicaset_map[&migration.slot_id].node_map;
+            if !members.contains_key(&migration.from)
+                || members.contains_key(&migration.to)
+                || !self.node_map.contains_key(&migration.to)
+            {
+                violations.push(Violation::StaleMigration {
+                    slot_id: migration.slot_id,
+                    from: migration.from.clone(),
+                    to: migration.to.clone(),
+                });
+            }
+        }
+
+        if let (true, Some(skew)) = (allocated, self.max_load_skew) {
+            violations.extend(self.load_violations(skew));
+        }
+        violations
+    }
+
+    fn load_violations(&self, skew: u32) -> Vec<Violation> {
+        let up: Vec<&str> = self
+            .node_names()
+            .into_iter()
+            .filter(|name| self.node_map[*name].is_up())
+            .collect();
+        if up.is_empty() {
+            return Vec::new();
+        }
+        let held: usize = up
+            .iter()
+            .map(|name| self.node_map[*name].slot_set.len())
+            .sum();
+        let average = held as f64 / up.len() as f64;
+        let max = (average * (100 + skew) as f64 / 100.0).ceil() as usize;
+        up.into_iter()
+            .filter_map(|name| {
+                let replicas = self.node_map[name].slot_set.len();
+                if replicas > max {
+                    Some(Violation::Overloaded {
+                        node: String::from(name),
+                        replicas,
+                        max,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Applies `mutation` to a copy of the cluster and keeps the result only
+    /// if it succeeds and breaks no invariant that held before.
+    ///
+    /// Failures and recoveries skip this: they happen whether or not the
+    /// layout allows them, and can leave violations behind that later
+    /// mutations are not blamed for.
+    pub(crate) fn checked<T>(
+        &mut self,
+        mutation: impl FnOnce(&mut ClusterManager) -> Result<T>,
+    ) -> Result<T> {
+        let before = self.check_invariants();
+        let mut next = self.clone();
+        let value = mutation(&mut next)?;
+        let broken = next
+            .check_invariants()
+            .into_iter()
+            .find(|violation| !before.contains(violation));
+        if let Some(violation) = broken {
+            return Err(Error::InvariantViolated(violation));
+        }
+        *self = next;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use consistent_hash::{DomainLevel, Location, PlacementRule};
+
+    fn new_cluster() -> ClusterManager {
+        let mut cm = ClusterManager::with_replica_num(128, 3).unwrap();
+        cm.allocate(&["aaa", "bbb", "ccc", "ddd"]).unwrap();
+        cm
+    }
+
+    #[test]
+    fn test_mutations_keep_invariants() {
+        let mut cm = new_cluster();
+        assert!(cm.check_invariants().is_empty());
+        for name in &["eee", "fff", "ggg", "hhh", "iii"] {
+            let migrated = cm.scale(name).unwrap();
+            let share = cm.node(name).unwrap().slot_set().len();
+            assert_eq!(migrated.len(), share);
+            let nodes_num = cm.node_names().len() as u64;
+            assert_eq!(share as u64, 128 / nodes_num + 256 / nodes_num);
+        }
+        cm.set_max_load_skew(Some(10));
+        assert!(cm.check_invariants().is_empty());
+        cm.fail_node("aaa").unwrap();
+        cm.re_replicate().unwrap();
+        assert!(cm.check_invariants().is_empty());
+    }
+
+    #[test]
+    fn test_broken_state() {
+        let mut cm = new_cluster();
+        let primary = String::from(cm.replica_set(0).unwrap().get_primary().unwrap());
+        cm.replicaset_map
+            .get_mut(&0)
+            .unwrap()
+            .assign(&primary, Role::Secondary);
+        let violations = cm.check_invariants();
+        assert!(violations.contains(&Violation::PrimaryCount {
+            slot_id: 0,
+            primaries: 0
+        }));
+        assert!(violations.contains(&Violation::SlotSetMismatch {
+            node: primary.clone(),
+            slot_id: 0,
+            role: Role::Primary
+        }));
+
+        let mut cm = new_cluster();
+        let secondary = cm
+            .replica_set(0)
+            .unwrap()
+            .node_map()
+            .iter()
+            .find(|(_, role)| **role == Role::Secondary)
+            .map(|(name, _)| name.clone())
+            .unwrap();
+        cm.replicaset_map.get_mut(&0).unwrap().migrate(&secondary);
+        let violations = cm.check_invariants();
+        assert!(violations.contains(&Violation::ReplicaCount {
+            slot_id: 0,
+            expected: 3,
+            actual: 2
+        }));
+    }
+
+    #[test]
+    fn test_rejected_mutation() {
+        let mut cm = ClusterManager::with_replica_num(64, 3).unwrap();
+        cm.set_placement_rule(PlacementRule::spread(DomainLevel::Zone));
+        for (name, zone) in &[
+            ("aaa", "z1"),
+            ("bbb", "z1"),
+            ("ccc", "z2"),
+            ("ddd", "z2"),
+            ("eee", "z3"),
+            ("fff", "z3"),
+        ] {
+            cm.add_node(name).unwrap();
+            let location = Location {
+                zone: Some(String::from(*zone)),
+                ..Location::default()
+            };
+            cm.set_location(name, location).unwrap();
+        }
+        cm.allocate(&[]).unwrap();
+        let before = cm.snapshot();
+        // bbb would be left alone in z1 holding every slot
+        assert!(matches!(
+            cm.scale_in(&["aaa"]),
+            Err(Error::InvariantViolated(Violation::Overloaded { .. }))
+        ));
+        assert_eq!(cm.snapshot(), before);
+        cm.set_max_load_skew(None);
+        cm.scale_in(&["aaa"]).unwrap();
+        assert_eq!(cm.node("bbb").unwrap().slot_set().len(), 64);
+    }
+
+    #[test]
+    fn test_failure_always_applies() {
+        let mut cm = new_cluster();
+        for name in &["eee", "fff", "ggg", "hhh"] {
+            cm.add_node(name).unwrap();
+        }
+        let failover = cm.fail_node("aaa").unwrap();
+        assert_eq!(cm.down_nodes(), vec!["aaa"]);
+        assert!(matches!(
+            failover.violations.first(),
+            Some(Violation::Overloaded { .. })
+        ));
+        // the overload was there before, so balancing is not blamed for it
+        cm.balance_primaries().unwrap();
+        cm.recover_node("aaa").unwrap();
+        assert!(cm.down_nodes().is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+use crate::cluster::ClusterManager;
+use crate::error::Result;
+use consistent_hash::Role;
+use std::collections::HashMap;
+
+impl ClusterManager {
+    /// Number of slots every live node leads, sorted by node name.
+    pub fn primary_counts(&self) -> Vec<(&str, usize)> {
+        self.node_names()
+            .into_iter()
+            .filter(|name| self.node_map[*name].is_up())
+            .map(|name| {
+                let led = self.node_map[name]
+                    .slot_set
+                    .iter()
+                    .filter(|(_, role)| *role == Role::Primary)
+                    .count();
+                (name, led)
+            })
+            .collect()
+    }
+
+    /// Evens out how many slots each live node leads by swapping roles
+    /// between a primary and a live secondary of the same replica set, as a
+    /// preferred-leader election would; no replica moves.
+    ///
+    /// Each swap takes a slot from the node leading the most and hands it to
+    /// the secondary leading the fewest, while that narrows the gap, so the
+    /// counts end up within one of each other unless the replica sets
+    /// prevent it. Returns `(slot_id, old primary, new primary)` in the
+    /// order applied.
+    pub fn balance_primaries(&mut self) -> Result<Vec<(u64, String, String)>> {
+        self.checked(|cm| Ok(cm.balance_primaries_unchecked()))
+    }
+
+    pub(crate) fn balance_primaries_unchecked(&mut self) -> Vec<(u64, String, String)> {
+        let mut led: HashMap<String, Vec<u64>> = HashMap::new();
+        for (name, _) in self.primary_counts() {
+            let mut slots: Vec<u64> = self.node_map[name]
+                .slot_set
+                .iter()
+                .filter(|(_, role)| *role == Role::Primary)
+                .map(|(slot_id, _)| *slot_id)
+                .collect();
+            slots.sort_unstable();
+            led.insert(String::from(name), slots);
+        }
+
+        let mut swaps = Vec::new();
+        while let Some((slot_id, from, to)) = self.next_leader_swap(&led) {
+            let role = self.replicaset_map[&slot_id].node_map[&to];
+            self.set_role(slot_id, &from, role);
+            self.set_role(slot_id, &to, Role::Primary);
+            led.get_mut(&from).unwrap().retain(|id| *id != slot_id);
+            let slots = led.get_mut(&to).unwrap();
+            let pos = slots.binary_search(&slot_id).unwrap_or_else(|pos| pos);
+            slots.insert(pos, slot_id);
+            swaps.push((slot_id, from, to));
+        }
+        swaps
+    }
+
+    /// Finds the swap that moves a slot from the busiest possible leader to
+    /// the idlest promotable secondary leading at least two fewer slots.
+    fn next_leader_swap(&self, led: &HashMap<String, Vec<u64>>) -> Option<(u64, String, String)> {
+        let mut donors: Vec<&String> = led.keys().collect();
+        donors.sort_unstable_by_key(|name| (std::cmp::Reverse(led[*name].len()), *name));
+        for donor in donors {
+            let count = led[donor].len();
+            let best = led[donor]
+                .iter()
+                .flat_map(|slot_id| {
+                    self.replicaset_map[slot_id]
+                        .node_map
+                        .iter()
+                        .filter(|(name, role)| role.can_promote() && led.contains_key(*name))
+                        .map(move |(name, _)| (led[name].len(), name, *slot_id))
+                })
+                .filter(|(other, _, _)| other + 1 < count)
+                .min();
+            if let Some((_, to, slot_id)) = best {
+                return Some((slot_id, donor.clone(), to.clone()));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spread(cm: &ClusterManager) -> usize {
+        let counts: Vec<usize> = cm.primary_counts().into_iter().map(|(_, n)| n).collect();
+        counts.iter().max().unwrap() - counts.iter().min().unwrap()
+    }
+
+    #[test]
+    fn test_balance_primaries() {
+        let mut cm = ClusterManager::with_replica_num(128, 3).unwrap();
+        cm.allocate(&["aaa", "bbb", "ccc", "ddd"]).unwrap();
+        for name in &["eee", "fff", "ggg"] {
+            cm.scale(name).unwrap();
+        }
+        cm.fail_node("aaa").unwrap();
+        cm.recover_node("aaa").unwrap();
+        assert!(spread(&cm) > 1);
+
+        let before: Vec<Vec<String>> = (0..128)
+            .map(|slot_id| {
+                let mut names: Vec<String> = cm
+                    .replica_set(slot_id)
+                    .unwrap()
+                    .node_map()
+                    .keys()
+                    .cloned()
+                    .collect();
+                names.sort_unstable();
+                names
+            })
+            .collect();
+        let swaps = cm.balance_primaries().unwrap();
+        assert!(!swaps.is_empty());
+        assert!(spread(&cm) <= 1);
+        for (slot_id, names) in before.iter().enumerate() {
+            let replset = cm.replica_set(slot_id as u64).unwrap();
+            let mut now: Vec<String> = replset.node_map().keys().cloned().collect();
+            now.sort_unstable();
+            assert_eq!(&now, names);
+        }
+        for (slot_id, _, to) in &swaps {
+            assert_eq!(
+                cm.replica_set(*slot_id).unwrap().get_primary(),
+                Some(to.as_str())
+            );
+        }
+        assert!(cm.balance_primaries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_only_secondaries_lead() {
+        let mut cm = ClusterManager::with_roles(8, &[Role::Primary, Role::Learner]).unwrap();
+        cm.allocate(&["aaa", "bbb"]).unwrap();
+        let primary = cm.replica_set(0).unwrap().get_primary().map(String::from);
+        let (from, to) = match primary.as_deref() {
+            Some("aaa") => ("aaa", "bbb"),
+            _ => ("bbb", "aaa"),
+        };
+        cm.set_role(0, from, Role::Learner);
+        cm.set_role(0, to, Role::Primary);
+        assert_eq!(spread(&cm), 2);
+        // every slot the busier node leads has only a learner to hand it to
+        assert!(cm.balance_primaries().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,22 @@
+mod batch;
+mod cluster;
+mod error;
+mod failover;
+mod invariant;
+mod leader;
+mod migration;
+mod placement;
+mod snapshot;
+
+pub use batch::BatchScale;
+pub use cluster::{ClusterManager, Node, NodeState, ReplicaSet, DEFAULT_REPLICA_NUM};
+pub use consistent_hash::{
+    DomainConstraint, DomainLevel, Location, MigrationLimits, MigrationSchedule, PlacementRule,
+    Role, Transfer, Wave,
+};
+pub use error::{Error, Result};
+pub use failover::{Failover, ReReplication};
+pub use invariant::{Violation, DEFAULT_MAX_LOAD_SKEW};
+pub use migration::{Route, SlotMigration, SlotState};
+pub use placement::{DomainStats, PlacementViolation};
+pub use snapshot::{ReplicaSetSnapshot, ReplicaSnapshot, Snapshot, SNAPSHOT_VERSION};
@@ -0,0 +1,366 @@
+use crate::cluster::ClusterManager;
+use crate::error::{Error, Result};
+use consistent_hash::Role;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What a node is doing with one slot, as in Redis Cluster resharding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlotState {
+    Stable,
+    /// The node still owns the slot while its keys move to `to`.
+    Migrating {
+        to: String,
+    },
+    /// The node receives the slot's keys from `from`.
+    Importing {
+        from: String,
+    },
+}
+
+/// A replica moving from `from` to `to` one batch of keys at a time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotMigration {
+    pub slot_id: u64,
+    pub from: String,
+    pub to: String,
+    /// Keys the slot held when the migration began.
+    pub keys: u64,
+    pub keys_moved: u64,
+}
+
+impl SlotMigration {
+    pub fn keys_left(&self) -> u64 {
+        self.keys - self.keys_moved
+    }
+}
+
+impl fmt::Display for SlotMigration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "slot {} {} -> {} => moved keys: {}/{}",
+            self.slot_id, self.from, self.to, self.keys_moved, self.keys
+        )
+    }
+}
+
+/// Where a client sends a request for a key of some slot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    /// The primary, which owns the slot until its migration finishes.
+    pub node: String,
+    /// While the primary migrates, keys it no longer has are served here
+    /// after an ASK redirect.
+    pub ask: Option<String>,
+}
+
+impl ClusterManager {
+    /// Migrations in progress, sorted by slot id.
+    pub fn migrations(&self) -> Vec<&SlotMigration> {
+        self.migrations.values().collect()
+    }
+
+    pub fn migration(&self, slot_id: u64) -> Option<&SlotMigration> {
+        self.migrations.get(&slot_id)
+    }
+
+    pub fn slot_state(&self, slot_id: u64, name: &str) -> SlotState {
+        match self.migrations.get(&slot_id) {
+            Some(m) if m.from == name => SlotState::Migrating { to: m.to.clone() },
+            Some(m) if m.to == name => SlotState::Importing {
+                from: m.from.clone(),
+            },
+            _ => SlotState::Stable,
+        }
+    }
+
+    /// Starts moving the replica `from` holds of `slot_id`, with `keys` keys
+    /// in it, to `to`; the source marks the slot migrating and the target
+    /// importing.
+    ///
+    /// Both nodes must be up. The replica set does not change until
+    /// `finish_migration`, and other operations leave the slot where it is
+    /// meanwhile. A slot moves through one migration at a time.
+    pub fn begin_migration(&mut self, slot_id: u64, from: &str, to: &str, keys: u64) -> Result<()> {
+        let migration = SlotMigration {
+            slot_id,
+            from: String::from(from),
+            to: String::from(to),
+            keys,
+            keys_moved: 0,
+        };
+        self.check_migration(&migration)?;
+        for name in &[from, to] {
+            if !self.node_map[*name].is_up() {
+                return Err(Error::InvalidMigration(format!("node {} is down", name)));
+            }
+        }
+        if !self.admits_move(slot_id, from, to) {
+            return Err(Error::PlacementUnsatisfiable(slot_id));
+        }
+        self.migrations.insert(slot_id, migration);
+        Ok(())
+    }
+
+    pub(crate) fn check_migration(&self, migration: &SlotMigration) -> Result<()> {
+        let slot_id = migration.slot_id;
+        let replset = self
+            .replicaset_map
+            .get(&slot_id)
+            .ok_or_else(|| Error::InvalidMigration(format!("unknown slot {}", slot_id)))?;
+        for name in &[&migration.from, &migration.to] {
+            if !self.node_map.contains_key(name.as_str()) {
+                return Err(Error::NodeNotFound(name.to_string()));
+            }
+        }
+        if self.migrations.contains_key(&slot_id) {
+            return Err(Error::MigrationInProgress(slot_id));
+        }
+        if !replset.node_map.contains_key(&migration.from) {
+            return Err(Error::InvalidMigration(format!(
+                "node {} holds no replica of slot {}",
+                migration.from, slot_id
+            )));
+        }
+        if replset.node_map.contains_key(&migration.to) {
+            return Err(Error::InvalidMigration(format!(
+                "node {} already holds slot {}",
+                migration.to, slot_id
+            )));
+        }
+        if migration.keys_moved > migration.keys {
+            return Err(Error::InvalidMigration(format!(
+                "slot {} moved {} of {} keys",
+                slot_id, migration.keys_moved, migration.keys
+            )));
+        }
+        Ok(())
+    }
+
+    fn migration_mut(&mut self, slot_id: u64) -> Result<&mut SlotMigration> {
+        self.migrations
+            .get_mut(&slot_id)
+            .ok_or(Error::NoMigration(slot_id))
+    }
+
+    /// Copies up to `keys` more keys of `slot_id` to the target and returns
+    /// how many are left.
+    pub fn migrate_keys(&mut self, slot_id: u64, keys: u64) -> Result<u64> {
+        let migration = self.migration_mut(slot_id)?;
+        migration.keys_moved += keys.min(migration.keys_left());
+        Ok(migration.keys_left())
+    }
+
+    /// Hands the replica to the target once every key has moved, keeping its
+    /// current role, and returns the slot to stable on both nodes.
+    pub fn finish_migration(&mut self, slot_id: u64) -> Result<SlotMigration> {
+        let migration = self.migration_mut(slot_id)?;
+        if migration.keys_left() > 0 {
+            return Err(Error::MigrationIncomplete {
+                slot_id,
+                keys_left: migration.keys_left(),
+            });
+        }
+        let migration = migration.clone();
+        if !self.node_map[&migration.to].is_up() {
+            return Err(Error::InvalidMigration(format!(
+                "node {} is down",
+                migration.to
+            )));
+        }
+        self.checked(|cm| {
+            cm.migrations.remove(&slot_id);
+            let role: Role = cm.replicaset_map[&slot_id].node_map[&migration.from];
+            cm.move_replica(slot_id, role, &migration.from, &migration.to);
+            Ok(migration)
+        })
+    }
+
+    /// Gives up on the migration of `slot_id`; the source kept ownership
+    /// throughout, so the keys copied so far are simply dropped by the
+    /// target.
+    pub fn abort_migration(&mut self, slot_id: u64) -> Result<SlotMigration> {
+        self.migrations
+            .remove(&slot_id)
+            .ok_or(Error::NoMigration(slot_id))
+    }
+
+    /// Where requests for `slot_id` go, or `None` before `allocate`.
+    pub fn route(&self, slot_id: u64) -> Option<Route> {
+        let primary = self.replicaset_map.get(&slot_id)?.get_primary()?;
+        let ask = match self.slot_state(slot_id, primary) {
+            SlotState::Migrating { to } => Some(to),
+            _ => None,
+        };
+        Some(Route {
+            node: String::from(primary),
+            ask,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_cluster() -> ClusterManager {
+        let mut cm = ClusterManager::with_replica_num(16, 2).unwrap();
+        cm.allocate(&["aaa", "bbb", "ccc"]).unwrap();
+        cm.add_node("ddd").unwrap();
+        cm
+    }
+
+    fn primary(cm: &ClusterManager, slot_id: u64) -> String {
+        String::from(cm.replica_set(slot_id).unwrap().get_primary().unwrap())
+    }
+
+    #[test]
+    fn test_migration() {
+        let mut cm = new_cluster();
+        let from = primary(&cm, 0);
+        cm.begin_migration(0, &from, "ddd", 100).unwrap();
+        assert_eq!(
+            cm.slot_state(0, &from),
+            SlotState::Migrating {
+                to: String::from("ddd")
+            }
+        );
+        assert_eq!(
+            cm.slot_state(0, "ddd"),
+            SlotState::Importing { from: from.clone() }
+        );
+        assert_eq!(
+            cm.route(0),
+            Some(Route {
+                node: from.clone(),
+                ask: Some(String::from("ddd"))
+            })
+        );
+
+        assert_eq!(cm.migrate_keys(0, 60).unwrap(), 40);
+        assert_eq!(
+            cm.finish_migration(0),
+            Err(Error::MigrationIncomplete {
+                slot_id: 0,
+                keys_left: 40
+            })
+        );
+        assert_eq!(cm.migrate_keys(0, 60).unwrap(), 0);
+        let done = cm.finish_migration(0).unwrap();
+        assert_eq!(done.keys_moved, 100);
+        assert!(cm.migrations().is_empty());
+        assert_eq!(cm.slot_state(0, "ddd"), SlotState::Stable);
+        assert_eq!(
+            cm.route(0),
+            Some(Route {
+                node: String::from("ddd"),
+                ask: None
+            })
+        );
+        assert!(!cm.replica_set(0).unwrap().node_map().contains_key(&from));
+        assert!(cm.check_invariants().is_empty());
+    }
+
+    #[test]
+    fn test_abort_migration() {
+        let mut cm = new_cluster();
+        let from = primary(&cm, 1);
+        cm.begin_migration(1, &from, "ddd", 10).unwrap();
+        cm.migrate_keys(1, 5).unwrap();
+        assert_eq!(
+            cm.begin_migration(1, &from, "ddd", 10),
+            Err(Error::MigrationInProgress(1))
+        );
+        assert_eq!(cm.abort_migration(1).unwrap().keys_moved, 5);
+        assert_eq!(cm.abort_migration(1), Err(Error::NoMigration(1)));
+        assert_eq!(cm.slot_state(1, &from), SlotState::Stable);
+        assert_eq!(primary(&cm, 1), from);
+    }
+
+    #[test]
+    fn test_invalid_migration() {
+        let mut cm = new_cluster();
+        let from = primary(&cm, 2);
+        assert!(matches!(
+            cm.begin_migration(2, "ddd", &from, 1),
+            Err(Error::InvalidMigration(_))
+        ));
+        assert_eq!(
+            cm.begin_migration(2, &from, "zzz", 1),
+            Err(Error::NodeNotFound(String::from("zzz")))
+        );
+        assert!(matches!(
+            cm.begin_migration(99, &from, "ddd", 1),
+            Err(Error::InvalidMigration(_))
+        ));
+        assert_eq!(cm.migrate_keys(2, 1), Err(Error::NoMigration(2)));
+    }
+
+    #[test]
+    fn test_migrating_slot_is_pinned() {
+        let mut cm = new_cluster();
+        cm.set_max_load_skew(None);
+        let from = primary(&cm, 3);
+        cm.begin_migration(3, &from, "ddd", 10).unwrap();
+        let replset = cm.replica_set(3).unwrap().node_map().clone();
+        cm.add_node("eee").unwrap();
+        assert_eq!(cm.replica_set(3).unwrap().node_map(), &replset);
+        assert_eq!(
+            cm.route(3),
+            Some(Route {
+                node: from,
+                ask: Some(String::from("ddd"))
+            })
+        );
+    }
+
+    #[test]
+    fn test_failed_source_aborts_migration() {
+        let mut cm = new_cluster();
+        cm.set_max_load_skew(None);
+        let from = primary(&cm, 3);
+        cm.begin_migration(3, &from, "ddd", 10).unwrap();
+        cm.migrate_keys(3, 4).unwrap();
+
+        let failover = cm.fail_node(&from).unwrap();
+        assert_eq!(failover.aborted.len(), 1);
+        assert_eq!(failover.aborted[0].keys_moved, 4);
+        assert!(cm.migrations().is_empty());
+        let route = cm.route(3).unwrap();
+        assert_ne!(route.node, from);
+        assert!(cm.node(&route.node).unwrap().is_up());
+        assert_eq!(route.ask, None);
+        assert_eq!(cm.slot_state(3, "ddd"), SlotState::Stable);
+        assert!(matches!(
+            cm.begin_migration(3, &from, "ddd", 10),
+            Err(Error::InvalidMigration(_))
+        ));
+        assert!(cm.migrations().is_empty());
+
+        cm.re_replicate().unwrap();
+        assert!(cm.node(&from).unwrap().slot_set().is_empty());
+        assert!(cm.check_invariants().is_empty());
+    }
+
+    #[test]
+    fn test_failed_target_aborts_migration() {
+        let mut cm = new_cluster();
+        cm.set_max_load_skew(None);
+        let from = primary(&cm, 4);
+        cm.begin_migration(4, &from, "ddd", 10).unwrap();
+
+        let failover = cm.fail_node("ddd").unwrap();
+        let aborted: Vec<u64> = failover.aborted.iter().map(|m| m.slot_id).collect();
+        assert_eq!(aborted, vec![4]);
+        assert_eq!(
+            cm.route(4),
+            Some(Route {
+                node: from.clone(),
+                ask: None
+            })
+        );
+        assert_eq!(cm.slot_state(4, &from), SlotState::Stable);
+        assert_eq!(cm.finish_migration(4), Err(Error::NoMigration(4)));
+    }
+}
@@ -0,0 +1,202 @@
+use crate::cluster::ClusterManager;
+use crate::error::{Error, Result};
+use consistent_hash::{DomainLevel, Location, PlacementRule, Role};
+use std::collections::BTreeMap;
+
+/// Replicas of one slot that overfill a failure domain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlacementViolation {
+    pub slot_id: u64,
+    pub level: DomainLevel,
+    pub domain: String,
+    /// Nodes holding the slot in `domain`, sorted.
+    pub nodes: Vec<String>,
+}
+
+/// What the nodes of one failure domain hold together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DomainStats {
+    pub domain: String,
+    pub nodes: usize,
+    pub primary_slots: usize,
+    pub secondary_slots: usize,
+    pub non_voting_slots: usize,
+}
+
+impl ClusterManager {
+    pub fn placement_rule(&self) -> &PlacementRule {
+        &self.rule
+    }
+
+    /// Replaces the rule that `allocate` and `scale_at` follow.
+    pub fn set_placement_rule(&mut self, rule: PlacementRule) {
+        self.rule = rule;
+    }
+
+    /// Places the node `name` in the failure-domain hierarchy.
+    pub fn set_location(&mut self, name: &str, location: Location) -> Result<()> {
+        let node = self
+            .node_map
+            .get_mut(name)
+            .ok_or_else(|| Error::NodeNotFound(String::from(name)))?;
+        node.location = location;
+        Ok(())
+    }
+
+    pub(crate) fn domain_of<'a>(&'a self, name: &'a str, level: DomainLevel) -> &'a str {
+        match self.node_map.get(name) {
+            Some(node) => node.location.domain(name, level),
+            None => name,
+        }
+    }
+
+    /// Reports every slot whose replicas overfill a failure domain.
+    pub fn validate_placement(&self) -> Vec<PlacementViolation> {
+        let mut violations = Vec::new();
+        for slot_id in 0..self.max_slot_id {
+            let replset = &self.replicaset_map[&slot_id];
+            for c in &self.rule.constraints {
+                let mut domains: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+                for name in replset.node_map.keys() {
+                    domains
+                        .entry(self.domain_of(name, c.level))
+                        .or_default()
+                        .push(name.clone());
+                }
+                for (domain, mut nodes) in domains {
+                    if nodes.len() > c.max_replicas {
+                        nodes.sort_unstable();
+                        violations.push(PlacementViolation {
+                            slot_id,
+                            level: c.level,
+                            domain: String::from(domain),
+                            nodes,
+                        });
+                    }
+                }
+            }
+        }
+        violations
+    }
+
+    /// Counts nodes and slot replicas per domain at `level`, sorted by
+    /// domain.
+    pub fn domain_stats(&self, level: DomainLevel) -> Vec<DomainStats> {
+        let mut domains: BTreeMap<&str, DomainStats> = BTreeMap::new();
+        for name in self.node_names() {
+            let domain = self.domain_of(name, level);
+            let stats = domains.entry(domain).or_insert_with(|| DomainStats {
+                domain: String::from(domain),
+                nodes: 0,
+                primary_slots: 0,
+                secondary_slots: 0,
+                non_voting_slots: 0,
+            });
+            stats.nodes += 1;
+            for (_, role) in &self.node_map[name].slot_set {
+                match role {
+                    Role::Primary => stats.primary_slots += 1,
+                    Role::Secondary => stats.secondary_slots += 1,
+                    _ => stats.non_voting_slots += 1,
+                }
+            }
+        }
+        domains.into_values().collect()
+    }
+
+    pub fn show_domains(&self, level: DomainLevel) {
+        for stats in self.domain_stats(level) {
+            println!(
+                "{} {}: nodes {}, primary slots count: {}, secondary slots count: {}, \
+                 non-voting slots count: {}",
+                level,
+                stats.domain,
+                stats.nodes,
+                stats.primary_slots,
+                stats.secondary_slots,
+                stats.non_voting_slots
+            );
+        }
+        println!("placement violations: {}", self.validate_placement().len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone(zone: &str) -> Location {
+        Location {
+            zone: Some(String::from(zone)),
+            ..Location::default()
+        }
+    }
+
+    fn new_cluster() -> ClusterManager {
+        let mut cm = ClusterManager::with_replica_num(128, 3).unwrap();
+        cm.set_placement_rule(PlacementRule::spread(DomainLevel::Zone));
+        for (name, z) in &[
+            ("aaa", "z1"),
+            ("bbb", "z1"),
+            ("ccc", "z2"),
+            ("ddd", "z2"),
+            ("eee", "z3"),
+            ("fff", "z3"),
+        ] {
+            cm.add_node(name).unwrap();
+            cm.set_location(name, zone(z)).unwrap();
+        }
+        cm.allocate(&[]).unwrap();
+        cm
+    }
+
+    #[test]
+    fn test_zone_rule() {
+        let mut cm = new_cluster();
+        assert!(cm.validate_placement().is_empty());
+        let domains = cm.domain_stats(DomainLevel::Zone);
+        assert_eq!(domains.len(), 3);
+        for stats in &domains {
+            assert_eq!(stats.nodes, 2);
+            assert_eq!(stats.primary_slots + stats.secondary_slots, 128);
+            assert_eq!(stats.non_voting_slots, 0);
+        }
+        for name in cm.node_names() {
+            let held = cm.node(name).unwrap().slot_set().len();
+            assert!((63..=65).contains(&held), "{} holds {}", name, held);
+        }
+
+        let migrated = cm.scale_at("ggg", zone("z1")).unwrap();
+        assert!(!migrated.is_empty());
+        assert!(cm.validate_placement().is_empty());
+        let slots: Vec<u64> = migrated.iter().map(|(slot_id, _)| *slot_id).collect();
+        let mut unique = slots.clone();
+        unique.dedup();
+        assert_eq!(slots, unique);
+        for slot_id in 0..128 {
+            assert_eq!(cm.replica_set(slot_id).unwrap().node_map().len(), 3);
+        }
+
+        // a node outside every zone is its own zone and takes any slot
+        cm.scale("hhh").unwrap();
+        assert!(cm.validate_placement().is_empty());
+    }
+
+    #[test]
+    fn test_unsatisfiable_rule() {
+        let mut cm = ClusterManager::with_replica_num(16, 3).unwrap();
+        cm.set_placement_rule(PlacementRule::spread(DomainLevel::Zone));
+        cm.add_node("aaa").unwrap();
+        cm.set_location("aaa", zone("z1")).unwrap();
+        cm.add_node("bbb").unwrap();
+        cm.set_location("bbb", zone("z1")).unwrap();
+        assert_eq!(cm.allocate(&["ccc"]), Err(Error::PlacementUnsatisfiable(0)));
+        assert!(cm.node("ccc").is_none());
+        assert_eq!(
+            cm.set_location("ccc", zone("z2")),
+            Err(Error::NodeNotFound(String::from("ccc")))
+        );
+        cm.allocate(&["ccc", "ddd"]).unwrap();
+        assert!(cm.validate_placement().is_empty());
+    }
+}
@@ -0,0 +1,388 @@
+use crate::cluster::{ClusterManager, Node, NodeState};
+use crate::error::{Error, Result};
+use crate::migration::SlotMigration;
+use consistent_hash::{Location, PlacementRule, Role};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// Version written into every snapshot; loading rejects newer ones.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicaSnapshot {
+    pub node: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicaSetSnapshot {
+    pub slot_id: u64,
+    /// Replicas sorted by role, primary first.
+    pub replicas: Vec<ReplicaSnapshot>,
+}
+
+/// The full topology of a `ClusterManager` in a stable, diffable form.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub slot_num: u64,
+    pub replica_num: u64,
+    /// Role of every replica position; empty means the default layout.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<Role>,
+    /// Node names, sorted.
+    pub nodes: Vec<String>,
+    /// Locations of the nodes that have one.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub locations: BTreeMap<String, Location>,
+    #[serde(default)]
+    pub rule: PlacementRule,
+    /// Nodes that are down, sorted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub down_nodes: Vec<String>,
+    /// Replica sets sorted by slot id.
+    pub replica_sets: Vec<ReplicaSetSnapshot>,
+    /// Migrations in progress, sorted by slot id.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub migrations: Vec<SlotMigration>,
+}
+
+fn invalid(msg: String) -> Error {
+    Error::InvalidSnapshot(msg)
+}
+
+impl Snapshot {
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(|e| invalid(e.to_string()))
+    }
+
+    pub fn from_json(s: &str) -> Result<Snapshot> {
+        serde_json::from_str(s).map_err(|e| invalid(e.to_string()))
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).map_err(|e| invalid(e.to_string()))
+    }
+
+    pub fn from_toml(s: &str) -> Result<Snapshot> {
+        toml::from_str(s).map_err(|e| invalid(e.to_string()))
+    }
+
+    /// Writes the snapshot as TOML if `path` ends in `.toml`, JSON otherwise.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let data = if is_toml(path) {
+            self.to_toml()?
+        } else {
+            self.to_json()?
+        };
+        fs::write(path, data).map_err(|e| invalid(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Reads a snapshot written by `save`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Snapshot> {
+        let path = path.as_ref();
+        let data =
+            fs::read_to_string(path).map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+        if is_toml(path) {
+            Self::from_toml(&data)
+        } else {
+            Self::from_json(&data)
+        }
+    }
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "toml")
+}
+
+impl ClusterManager {
+    pub fn snapshot(&self) -> Snapshot {
+        let replica_sets = (0..self.max_slot_id)
+            .map(|slot_id| {
+                let mut replicas: Vec<ReplicaSnapshot> = self.replicaset_map[&slot_id]
+                    .node_map
+                    .iter()
+                    .map(|(node, role)| ReplicaSnapshot {
+                        node: node.clone(),
+                        role: *role,
+                    })
+                    .collect();
+                replicas.sort_by(|a, b| (a.role, &a.node).cmp(&(b.role, &b.node)));
+                ReplicaSetSnapshot { slot_id, replicas }
+            })
+            .collect();
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            slot_num: self.max_slot_id,
+            replica_num: self.replica_num,
+            roles: if self.roles == Role::layout(self.replica_num) {
+                Vec::new()
+            } else {
+                self.roles.clone()
+            },
+            nodes: self.node_names().into_iter().map(String::from).collect(),
+            locations: self
+                .node_map
+                .values()
+                .filter(|node| node.location != Location::default())
+                .map(|node| (node.name.clone(), node.location.clone()))
+                .collect(),
+            rule: self.rule.clone(),
+            down_nodes: self.down_nodes().into_iter().map(String::from).collect(),
+            replica_sets,
+            migrations: self.migrations.values().cloned().collect(),
+        }
+    }
+
+    /// Rebuilds a cluster from `snapshot`, checking that it is consistent.
+    pub fn from_snapshot(snapshot: &Snapshot) -> Result<ClusterManager> {
+        if snapshot.version == 0 || snapshot.version > SNAPSHOT_VERSION {
+            return Err(Error::UnsupportedSnapshotVersion(snapshot.version));
+        }
+        let mut cm = if snapshot.roles.is_empty() {
+            ClusterManager::with_replica_num(snapshot.slot_num, snapshot.replica_num)?
+        } else if snapshot.roles.len() as u64 != snapshot.replica_num {
+            return Err(invalid(format!(
+                "{} roles for {} replicas",
+                snapshot.roles.len(),
+                snapshot.replica_num
+            )));
+        } else {
+            ClusterManager::with_roles(snapshot.slot_num, &snapshot.roles)?
+        };
+        let names: Vec<&str> = snapshot.nodes.iter().map(String::as_str).collect();
+        cm.check_new_nodes(&names)?;
+        cm.add_nodes(&names);
+        for (name, location) in &snapshot.locations {
+            cm.set_location(name, location.clone())
+                .map_err(|_| invalid(format!("location of unknown node {}", name)))?;
+        }
+        cm.set_placement_rule(snapshot.rule.clone());
+        for name in &snapshot.down_nodes {
+            let node = cm
+                .node_map
+                .get_mut(name)
+                .ok_or_else(|| invalid(format!("unknown down node {}", name)))?;
+            node.state = NodeState::Down;
+        }
+
+        let mut seen = HashSet::new();
+        for replset in &snapshot.replica_sets {
+            if !seen.insert(replset.slot_id) {
+                return Err(invalid(format!("slot {} listed twice", replset.slot_id)));
+            }
+            if replset.replicas.len() as u64 > cm.replica_num {
+                return Err(invalid(format!(
+                    "slot {} has {} replicas, at most {} allowed",
+                    replset.slot_id,
+                    replset.replicas.len(),
+                    cm.replica_num
+                )));
+            }
+            let mut open_roles = cm.roles.clone();
+            for replica in &replset.replicas {
+                match open_roles.iter().position(|role| *role == replica.role) {
+                    Some(i) => {
+                        open_roles.remove(i);
+                    }
+                    None => {
+                        return Err(invalid(format!(
+                            "slot {} has too many {} replicas",
+                            replset.slot_id, replica.role
+                        )))
+                    }
+                }
+                let node: &mut Node = cm
+                    .node_map
+                    .get_mut(&replica.node)
+                    .ok_or_else(|| invalid(format!("unknown node {}", replica.node)))?;
+                let target = cm
+                    .replicaset_map
+                    .get_mut(&replset.slot_id)
+                    .ok_or_else(|| invalid(format!("unknown slot {}", replset.slot_id)))?;
+                if target.node_map.contains_key(&replica.node) {
+                    return Err(invalid(format!(
+                        "node {} holds slot {} twice",
+                        replica.node, replset.slot_id
+                    )));
+                }
+                target.assign(&replica.node, replica.role);
+                node.pickup_slot(replset.slot_id, replica.role);
+            }
+        }
+        if let Some(v) = cm.validate_placement().first() {
+            return Err(invalid(format!(
+                "slot {} has {} replicas in {} {}",
+                v.slot_id,
+                v.nodes.len(),
+                v.level,
+                v.domain
+            )));
+        }
+        for migration in &snapshot.migrations {
+            cm.check_migration(migration)
+                .map_err(|e| invalid(e.to_string()))?;
+            cm.migrations.insert(migration.slot_id, migration.clone());
+        }
+        Ok(cm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_cluster() -> ClusterManager {
+        let mut cm = ClusterManager::with_replica_num(64, 2).unwrap();
+        cm.allocate(&["aaa", "bbb", "ccc", "ddd"]).unwrap();
+        cm
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut cm = new_cluster();
+        let snapshot = cm.snapshot();
+        assert_eq!(snapshot.replica_sets.len(), 64);
+
+        let json = Snapshot::from_json(&snapshot.to_json().unwrap()).unwrap();
+        let toml = Snapshot::from_toml(&snapshot.to_toml().unwrap()).unwrap();
+        for loaded in &[json, toml] {
+            let other = ClusterManager::from_snapshot(loaded).unwrap();
+            assert_eq!(other.snapshot(), snapshot);
+            for name in cm.node_names() {
+                assert_eq!(
+                    cm.node(name).unwrap().slot_set(),
+                    other.node(name).unwrap().slot_set()
+                );
+            }
+        }
+
+        cm.scale("eee").unwrap();
+        cm.fail_node("bbb").unwrap();
+        let snapshot = cm.snapshot();
+        assert_eq!(snapshot.down_nodes, vec![String::from("bbb")]);
+        let other = ClusterManager::from_snapshot(&snapshot).unwrap();
+        assert_eq!(other.snapshot(), snapshot);
+
+        let path = std::env::temp_dir().join(format!(
+            "consistent-hash-variant-test-{}-round-trip.toml",
+            std::process::id()
+        ));
+        snapshot.save(&path).unwrap();
+        assert_eq!(Snapshot::load(&path).unwrap(), snapshot);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_snapshot() {
+        let cm = new_cluster();
+        let mut snapshot = cm.snapshot();
+        snapshot.version = 0;
+        assert_eq!(
+            ClusterManager::from_snapshot(&snapshot).unwrap_err(),
+            Error::UnsupportedSnapshotVersion(0)
+        );
+
+        let mut snapshot = cm.snapshot();
+        snapshot.nodes.retain(|name| name != "aaa");
+        assert!(ClusterManager::from_snapshot(&snapshot).is_err());
+
+        let mut snapshot = cm.snapshot();
+        snapshot.replica_sets[0].replicas[0].role = Role::Secondary;
+        assert!(ClusterManager::from_snapshot(&snapshot).is_err());
+
+        let mut snapshot = cm.snapshot();
+        snapshot.version = SNAPSHOT_VERSION + 1;
+        assert!(ClusterManager::from_snapshot(&snapshot).is_err());
+
+        let mut snapshot = cm.snapshot();
+        snapshot.rule = PlacementRule::spread(consistent_hash::DomainLevel::Zone);
+        for name in &["aaa", "bbb"] {
+            let location = Location {
+                zone: Some(String::from("z1")),
+                ..Location::default()
+            };
+            snapshot.locations.insert(String::from(*name), location);
+        }
+        assert!(ClusterManager::from_snapshot(&snapshot).is_err());
+        snapshot
+            .locations
+            .insert(String::from("eee"), Location::default());
+        assert!(ClusterManager::from_snapshot(&snapshot).is_err());
+    }
+
+    #[test]
+    fn test_roles() {
+        let mut cm =
+            ClusterManager::with_roles(32, &[Role::Primary, Role::Secondary, Role::Witness])
+                .unwrap();
+        cm.allocate(&["aaa", "bbb", "ccc", "ddd"]).unwrap();
+        let snapshot = cm.snapshot();
+        assert_eq!(snapshot.roles, cm.roles());
+        let json = snapshot.to_json().unwrap();
+        assert!(json.contains("\"Witness\""));
+        let other = ClusterManager::from_snapshot(&Snapshot::from_json(&json).unwrap()).unwrap();
+        assert_eq!(other.snapshot(), snapshot);
+    }
+
+    #[test]
+    fn test_migrations() {
+        let mut cm = new_cluster();
+        let members = cm.replica_set(0).unwrap().node_map().clone();
+        let from = cm
+            .replica_set(0)
+            .unwrap()
+            .get_primary()
+            .unwrap()
+            .to_string();
+        let to = cm
+            .node_names()
+            .into_iter()
+            .find(|name| !members.contains_key(*name))
+            .unwrap()
+            .to_string();
+        cm.begin_migration(0, &from, &to, 8).unwrap();
+        cm.migrate_keys(0, 3).unwrap();
+        let snapshot = cm.snapshot();
+        assert_eq!(snapshot.migrations.len(), 1);
+        for loaded in &[
+            Snapshot::from_json(&snapshot.to_json().unwrap()).unwrap(),
+            Snapshot::from_toml(&snapshot.to_toml().unwrap()).unwrap(),
+        ] {
+            let other = ClusterManager::from_snapshot(loaded).unwrap();
+            assert_eq!(other.migration(0), cm.migration(0));
+        }
+
+        let mut snapshot = cm.snapshot();
+        snapshot.migrations[0].keys_moved = 9;
+        assert!(ClusterManager::from_snapshot(&snapshot).is_err());
+        let mut snapshot = cm.snapshot();
+        snapshot.migrations[0].to = from;
+        assert!(ClusterManager::from_snapshot(&snapshot).is_err());
+    }
+
+    #[test]
+    fn test_locations() {
+        let mut cm = new_cluster();
+        let location = Location {
+            region: Some(String::from("eu")),
+            host: Some(String::from("h1")),
+            ..Location::default()
+        };
+        cm.set_location("bbb", location.clone()).unwrap();
+        cm.set_placement_rule("region=2,host=1".parse().unwrap());
+        let snapshot = cm.snapshot();
+        assert_eq!(snapshot.locations.len(), 1);
+        for loaded in &[
+            Snapshot::from_json(&snapshot.to_json().unwrap()).unwrap(),
+            Snapshot::from_toml(&snapshot.to_toml().unwrap()).unwrap(),
+        ] {
+            let other = ClusterManager::from_snapshot(loaded).unwrap();
+            assert_eq!(other.node("bbb").unwrap().location(), &location);
+            assert_eq!(other.placement_rule(), cm.placement_rule());
+        }
+    }
+}
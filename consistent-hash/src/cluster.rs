@@ -0,0 +1,913 @@
+use crate::error::{Error, Result};
+use crate::hash::{HashAlgorithm, PlacementHasher};
+use crate::migration::MigrationPlan;
+use crate::placement::{Location, PlacementRule};
+use crate::role::Role;
+use crate::weight::{Fill, DEFAULT_WEIGHT};
+use rand::{ChaChaRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// Default number of copies kept for every slot: one primary plus two
+/// secondaries.
+pub const DEFAULT_REPLICA_NUM: u64 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Slot {
+    pub id: u64,
+    pub role: Role,
+}
+
+#[derive(Debug, Clone)]
+pub struct VNode {
+    pub(crate) id: u64,
+    pub(crate) node_name: Option<String>,
+    pub(crate) slots: Vec<Slot>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub(crate) name: String,
+    pub(crate) vnode_set: HashSet<u64>,
+    pub(crate) location: Location,
+    pub(crate) weight: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClusterManager {
+    pub(crate) max_vnode_id: u64,
+    pub(crate) max_slot_id: u64,
+    pub(crate) nodes: HashMap<String, Node>,
+    pub(crate) vnodes: Vec<VNode>,
+    // vnode ids holding each slot, primary first
+    pub(crate) slot_vnodes: Vec<Vec<u64>>,
+    pub(crate) seed: u64,
+    pub(crate) rng: PlacementRng,
+    // allowed shortfall from the ideal per-node vnode count after `scale`
+    pub(crate) tolerance: u64,
+    pub(crate) rule: PlacementRule,
+    // copies kept for every slot, primary included
+    pub(crate) replica_num: u64,
+    pub(crate) roles: Vec<Role>,
+    pub(crate) hash_algorithm: HashAlgorithm,
+    // slot replicas a node may hold beyond its weighted share, as a fraction
+    // of it
+    pub(crate) load_epsilon: Option<f64>,
+    // vnodes the last placement passed a node at its load cap over for, and
+    // vnodes it placed
+    pub(crate) overflowed: usize,
+    pub(crate) placed: usize,
+}
+
+impl VNode {
+    fn new(id: u64) -> VNode {
+        VNode {
+            id,
+            node_name: None,
+            slots: Vec::new(),
+        }
+    }
+
+    pub(crate) fn assign(&mut self, node_name: Option<String>) {
+        self.node_name = node_name;
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn node_name(&self) -> Option<&str> {
+        self.node_name.as_deref()
+    }
+
+    pub fn slots(&self) -> &[Slot] {
+        &self.slots
+    }
+}
+
+impl Node {
+    pub(crate) fn new(name: &str) -> Node {
+        Node {
+            name: String::from(name),
+            vnode_set: HashSet::new(),
+            location: Location::default(),
+            weight: DEFAULT_WEIGHT,
+        }
+    }
+
+    pub(crate) fn pickup_vnode(&mut self, vnode_id: u64) {
+        self.vnode_set.insert(vnode_id);
+    }
+
+    pub(crate) fn drop_vnode(&mut self, vnode_id: u64) {
+        self.vnode_set.remove(&vnode_id);
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn vnode_set(&self) -> &HashSet<u64> {
+        &self.vnode_set
+    }
+
+    pub fn rack(&self) -> Option<&str> {
+        self.location.rack.as_deref()
+    }
+
+    pub fn location(&self) -> &Location {
+        &self.location
+    }
+
+    pub fn weight(&self) -> u64 {
+        self.weight
+    }
+}
+
+impl Default for ClusterManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub(crate) fn new_rng(seed: u64) -> ChaChaRng {
+    ChaChaRng::from_seed(&[seed as u32, (seed >> 32) as u32])
+}
+
+/// The placement generator, counting its draws so that a snapshot can
+/// resume it where it stopped.
+#[derive(Debug, Clone)]
+pub(crate) struct PlacementRng {
+    rng: ChaChaRng,
+    pub(crate) draws: u64,
+}
+
+impl PlacementRng {
+    /// Starts the generator of `seed` after `draws` draws.
+    pub(crate) fn new(seed: u64, draws: u64) -> PlacementRng {
+        let mut rng = new_rng(seed);
+        for _ in 0..draws {
+            rng.next_u32();
+        }
+        PlacementRng { rng, draws }
+    }
+}
+
+impl Rng for PlacementRng {
+    fn next_u32(&mut self) -> u32 {
+        self.draws += 1;
+        self.rng.next_u32()
+    }
+}
+
+impl ClusterManager {
+    /// Creates a cluster seeded from the OS; see `seed` to replay it.
+    pub fn new() -> ClusterManager {
+        Self::with_seed(rand::random())
+    }
+
+    /// Creates a cluster whose placement is fully determined by `seed`.
+    pub fn with_seed(seed: u64) -> ClusterManager {
+        ClusterManager {
+            max_vnode_id: 1024,
+            max_slot_id: 16,
+            nodes: HashMap::new(),
+            vnodes: Vec::new(),
+            slot_vnodes: Vec::new(),
+            seed,
+            rng: PlacementRng::new(seed, 0),
+            tolerance: 0,
+            rule: PlacementRule::default(),
+            replica_num: DEFAULT_REPLICA_NUM,
+            roles: Role::layout(DEFAULT_REPLICA_NUM),
+            hash_algorithm: HashAlgorithm::default(),
+            load_epsilon: None,
+            overflowed: 0,
+            placed: 0,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn tolerance(&self) -> u64 {
+        self.tolerance
+    }
+
+    /// Sets how many vnodes below the ideal per-node count `scale` may stop.
+    pub fn set_tolerance(&mut self, tolerance: u64) {
+        self.tolerance = tolerance;
+    }
+
+    pub fn replica_num(&self) -> u64 {
+        self.replica_num
+    }
+
+    /// Sets the number of copies kept for every slot, primary included,
+    /// all of them secondaries but the primary.
+    ///
+    /// Slots already placed are placed again with the new factor; vnodes
+    /// already allocated keep their owners, so check `validate_placement`.
+    pub fn set_replica_num(&mut self, replica_num: u64) -> Result<()> {
+        self.check_replica_num(replica_num)?;
+        self.replica_num = replica_num;
+        self.roles = Role::layout(replica_num);
+        if !self.slot_vnodes.is_empty() {
+            self.init_slots(self.max_slot_id)?;
+        }
+        Ok(())
+    }
+
+    pub fn vnode_num(&self) -> u64 {
+        self.vnodes.len() as u64
+    }
+
+    pub fn slot_num(&self) -> u64 {
+        self.slot_vnodes.len() as u64
+    }
+
+    pub fn node(&self, name: &str) -> Option<&Node> {
+        self.nodes.get(name)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.values()
+    }
+
+    /// Returns the node names sorted, for stable output.
+    pub fn node_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.nodes.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn vnode(&self, vnode_id: u64) -> Result<&VNode> {
+        self.vnodes
+            .get(vnode_id as usize)
+            .ok_or(Error::VNodeNotFound(vnode_id))
+    }
+
+    pub fn vnodes(&self) -> &[VNode] {
+        &self.vnodes
+    }
+
+    pub fn vnode_owner(&self, vnode_id: u64) -> Result<Option<&str>> {
+        self.vnode(vnode_id).map(VNode::node_name)
+    }
+
+    /// Returns the vnode ids holding `slot_id`, primary first.
+    pub fn slot_vnodes(&self, slot_id: u64) -> Result<&[u64]> {
+        self.slot_vnodes
+            .get(slot_id as usize)
+            .map(Vec::as_slice)
+            .ok_or(Error::SlotNotFound(slot_id))
+    }
+
+    pub fn init_vnodes(&mut self, vnode_num: u64) -> Result<()> {
+        if vnode_num == 0 {
+            return Err(Error::InvalidVNodeNum(vnode_num));
+        }
+        if !self.vnodes.is_empty() {
+            return Err(Error::VNodesAlreadyInitialized);
+        }
+        self.max_vnode_id = vnode_num;
+        for vnode_id in 0..self.max_vnode_id {
+            let vnode = VNode::new(vnode_id);
+            self.vnodes.push(vnode);
+        }
+        Ok(())
+    }
+
+    /// Every replica of a slot needs a vnode of its own, so there can be no
+    /// more replicas than vnodes.
+    pub(crate) fn check_replica_num(&self, replica_num: u64) -> Result<()> {
+        if replica_num == 0 || (!self.vnodes.is_empty() && replica_num > self.max_vnode_id) {
+            return Err(Error::InvalidReplicaNum(replica_num));
+        }
+        Ok(())
+    }
+
+    pub fn init_slots(&mut self, slot_num: u64) -> Result<()> {
+        if self.vnodes.is_empty() {
+            return Err(Error::VNodesNotInitialized);
+        }
+        if slot_num == 0 {
+            return Err(Error::InvalidSlotNum(slot_num));
+        }
+        self.check_replica_num(self.replica_num)?;
+        self.max_slot_id = slot_num;
+        for vnode in &mut self.vnodes {
+            vnode.slots.clear();
+        }
+        self.slot_vnodes.clear();
+
+        for slot_id in 0..self.max_slot_id {
+            let hc = self.hash_algorithm.hash(&slot_id.to_le_bytes());
+            let mut vnode_id = hc % self.max_vnode_id;
+
+            let mut vnode_ids = Vec::new();
+            for role in &self.roles {
+                let role = *role;
+                self.vnodes[vnode_id as usize]
+                    .slots
+                    .push(Slot { id: slot_id, role });
+                vnode_ids.push(vnode_id);
+                vnode_id = (vnode_id + 1) % self.max_vnode_id;
+            }
+            self.slot_vnodes.push(vnode_ids);
+        }
+        Ok(())
+    }
+
+    fn check_new_nodes(&self, names: &[&str]) -> Result<()> {
+        let mut seen = HashSet::new();
+        for name in names {
+            if self.nodes.contains_key(*name) || !seen.insert(*name) {
+                return Err(Error::DuplicateNode(String::from(*name)));
+            }
+        }
+        Ok(())
+    }
+
+    fn add_nodes(&mut self, names: &[&str]) {
+        for name in names {
+            self.nodes.insert(String::from(*name), Node::new(name));
+        }
+    }
+
+    /// Adds an empty node, e.g. to give it a rack or weight before `allocate`.
+    pub fn add_node(&mut self, name: &str) -> Result<()> {
+        self.check_new_nodes(&[name])?;
+        self.add_nodes(&[name]);
+        Ok(())
+    }
+
+    /// Adds `names` to the cluster and spreads every vnode over all nodes in
+    /// proportion to their weights.
+    ///
+    /// Each vnode goes to a random node with vnodes left in its quota, and
+    /// never where the placement rule forbids another replica of its slots.
+    /// Under `set_load_epsilon`, nodes at their load cap are left out while
+    /// any other node can take the vnode.
+    pub fn allocate(&mut self, names: &[&str]) -> Result<()> {
+        if self.vnodes.is_empty() {
+            return Err(Error::VNodesNotInitialized);
+        }
+        if self.vnodes.iter().any(|vnode| vnode.node_name.is_some()) {
+            return Err(Error::AlreadyAllocated);
+        }
+        self.check_new_nodes(names)?;
+        let available = self.nodes.len() + names.len();
+        if available < self.replica_num as usize {
+            return Err(Error::NotEnoughNodes {
+                required: self.replica_num as usize,
+                available,
+            });
+        }
+
+        self.add_nodes(names);
+        if let Err(e) = self.check_domains() {
+            self.nodes.retain(|name, _| !names.contains(&name.as_str()));
+            return Err(e);
+        }
+        let ns: Vec<String> = self.node_names().into_iter().map(String::from).collect();
+        let mut quotas = self.weighted_quotas(&ns, self.max_vnode_id);
+        let mut bound = self.load_bound(self.total_weight());
+        self.overflowed = 0;
+        self.placed = self.max_vnode_id as usize;
+
+        // vnodes holding the most slots have the fewest valid owners, so they
+        // pick first
+        let mut order: Vec<u64> = (0..self.max_vnode_id).collect();
+        order.sort_by_key(|v| Reverse(self.vnodes[*v as usize].slots.len()));
+        for i in order {
+            let slots = self.vnodes[i as usize].slots.len();
+            let mut fits: Vec<usize> = (0..ns.len()).filter(|k| self.fits(i, &ns[*k])).collect();
+            // nodes at their load cap are passed over while another can take
+            // the vnode
+            let under: Vec<usize> = fits
+                .iter()
+                .cloned()
+                .filter(|k| bound.admits(&ns[*k], slots))
+                .collect();
+            if under.len() < fits.len() {
+                self.overflowed += 1;
+                if !under.is_empty() {
+                    fits = under;
+                }
+            }
+            let quota_sum: u64 = fits.iter().map(|k| quotas[*k]).sum();
+            let ri = if quota_sum > 0 {
+                let mut r = self.rng.gen_range(0, quota_sum);
+                let mut picked = fits[0];
+                for k in &fits {
+                    if r < quotas[*k] {
+                        picked = *k;
+                        break;
+                    }
+                    r -= quotas[*k];
+                }
+                picked
+            } else if let Some(k) = fits.iter().min_by_key(|k| Fill {
+                load: self.nodes[&ns[**k]].vnode_set.len(),
+                weight: self.nodes[&ns[**k]].weight,
+                name: ns[**k].clone(),
+            }) {
+                *k
+            } else {
+                for vnode in &mut self.vnodes {
+                    vnode.assign(None);
+                }
+                for node in self.nodes.values_mut() {
+                    node.vnode_set.clear();
+                }
+                self.nodes.retain(|name, _| !names.contains(&name.as_str()));
+                return Err(Error::AntiAffinityUnsatisfiable(i));
+            };
+            quotas[ri] = quotas[ri].saturating_sub(1);
+            let name = &ns[ri];
+            bound.shift(None, name, slots);
+            if let Some(node) = self.nodes.get_mut(name) {
+                node.pickup_vnode(i);
+                self.vnodes[i as usize].assign(Some(name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the node `name` and moves vnodes to it from the most loaded
+    /// nodes, relative to their weights, until it is within `tolerance` of
+    /// its weighted share. Vnodes that would take it over its load cap stay
+    /// where they are.
+    ///
+    /// Returns the migration plan that was carried out, ordered by vnode id.
+    pub fn scale(&mut self, name: &str) -> Result<MigrationPlan> {
+        self.scale_weighted(name, DEFAULT_WEIGHT)
+    }
+
+    /// Like `scale`, for a node of the given weight: only the vnodes making
+    /// up its weighted share move, unlike `add_node` then `set_weight`,
+    /// which rebalances the whole cluster.
+    pub fn scale_weighted(&mut self, name: &str, weight: u64) -> Result<MigrationPlan> {
+        self.scale_weighted_at(name, weight, Location::default())
+    }
+
+    /// Like `scale_weighted`, for a node at `location`: the placement rule
+    /// sees the location while the vnodes are picked, so only the node's
+    /// share moves, unlike `add_node`, `set_location` then `set_weight`.
+    pub fn scale_weighted_at(
+        &mut self,
+        name: &str,
+        weight: u64,
+        location: Location,
+    ) -> Result<MigrationPlan> {
+        if weight == 0 {
+            return Err(Error::InvalidWeight(weight));
+        }
+        if self.vnodes.is_empty() {
+            return Err(Error::VNodesNotInitialized);
+        }
+        if let Some(vnode) = self.vnodes.iter().find(|v| v.node_name.is_none()) {
+            return Err(Error::VNodeNotAllocated(vnode.id));
+        }
+        self.check_new_nodes(&[name])?;
+
+        let total_weight = self.total_weight() + weight;
+        let target =
+            (self.max_vnode_id * weight / total_weight).saturating_sub(self.tolerance) as usize;
+
+        // every donor gives away its vnodes in a seeded random order, skipping
+        // those whose slots already have a replica in the new node's domain
+        let mut candidates: HashMap<String, Vec<u64>> = HashMap::new();
+        let mut heap: BinaryHeap<Fill> = BinaryHeap::new();
+        let names: Vec<String> = self.node_names().into_iter().map(String::from).collect();
+        for name_src in names {
+            let node_src = &self.nodes[&name_src];
+            let mut vnode_ids: Vec<u64> = node_src.vnode_set.iter().cloned().collect();
+            vnode_ids.sort_unstable();
+            heap.push(Fill {
+                load: vnode_ids.len(),
+                weight: node_src.weight,
+                name: name_src.clone(),
+            });
+            self.rng.shuffle(&mut vnode_ids);
+            candidates.insert(name_src, vnode_ids);
+        }
+
+        self.add_nodes(&[name]);
+        if let Some(node) = self.nodes.get_mut(name) {
+            node.weight = weight;
+            node.location = location;
+        }
+        let mut bound = self.load_bound(total_weight);
+        self.overflowed = 0;
+        let mut moves = Vec::new();
+        while moves.len() < target {
+            let donor = match heap.pop() {
+                Some(donor) => donor,
+                None => break,
+            };
+            // stealing only helps while the donor stays at least as full as
+            // the new node
+            if (donor.load as u64).saturating_sub(1) * weight
+                < (moves.len() as u64 + 1) * donor.weight
+            {
+                break;
+            }
+            let vnode_ids = &candidates[&donor.name];
+            let first = vnode_ids.iter().rposition(|v| self.fits(*v, name));
+            let pos = vnode_ids.iter().rposition(|v| {
+                bound.admits(name, self.vnodes[*v as usize].slots.len()) && self.fits(*v, name)
+            });
+            if pos != first {
+                self.overflowed += 1;
+            }
+            let pos = match pos {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let vnode_id = candidates.get_mut(&donor.name).unwrap().swap_remove(pos);
+            let slots = self.vnodes[vnode_id as usize].slots.len();
+            bound.shift(Some(&donor.name), name, slots);
+            if let Some(node_src) = self.nodes.get_mut(&donor.name) {
+                node_src.drop_vnode(vnode_id);
+            }
+            if let Some(node_dst) = self.nodes.get_mut(name) {
+                node_dst.pickup_vnode(vnode_id);
+            }
+            self.vnodes[vnode_id as usize].assign(Some(String::from(name)));
+            moves.push(self.migration_step(vnode_id, &donor.name, name));
+            heap.push(Fill {
+                load: donor.load - 1,
+                ..donor
+            });
+        }
+
+        self.placed = moves.len();
+        moves.sort_by_key(|m| m.vnode_id);
+        Ok(MigrationPlan {
+            added_nodes: vec![String::from(name)],
+            removed_nodes: Vec::new(),
+            steps: moves,
+        })
+    }
+
+    /// Removes the node `name`, handing each of its vnodes to the remaining
+    /// node with the fewest vnodes relative to its weight that the placement
+    /// rule allows for its slots, skipping nodes at their load cap unless no
+    /// other node is valid.
+    ///
+    /// Returns the migration plan that was carried out, ordered by vnode id.
+    /// Nothing is changed if some vnode has no valid destination.
+    pub fn decommission(&mut self, name: &str) -> Result<MigrationPlan> {
+        let node_src = self
+            .nodes
+            .get(name)
+            .ok_or_else(|| Error::NodeNotFound(String::from(name)))?;
+        let available = self.nodes.len() - 1;
+        if available < self.replica_num as usize {
+            return Err(Error::NotEnoughNodes {
+                required: self.replica_num as usize,
+                available,
+            });
+        }
+
+        // vnodes holding the most slots have the fewest valid destinations
+        let mut vnode_ids: Vec<u64> = node_src.vnode_set.iter().cloned().collect();
+        vnode_ids.sort_unstable();
+        vnode_ids.sort_by_key(|v| Reverse(self.vnodes[*v as usize].slots.len()));
+
+        // receivers are ordered by how full they would be after one more vnode
+        let mut heap: BinaryHeap<Reverse<Fill>> = self
+            .nodes
+            .values()
+            .filter(|node| node.name != name)
+            .map(|node| {
+                Reverse(Fill {
+                    load: node.vnode_set.len() + 1,
+                    weight: node.weight,
+                    name: node.name.clone(),
+                })
+            })
+            .collect();
+
+        // moves are tried on a scratch copy so later ones see earlier ones
+        let mut scratch = self.clone();
+        let mut bound = self.load_bound(self.total_weight() - node_src.weight);
+        let mut overflowed = 0;
+        let mut moves = Vec::new();
+        for vnode_id in vnode_ids {
+            let slots = self.vnodes[vnode_id as usize].slots.len();
+            let mut skipped = Vec::new();
+            let mut dst = None;
+            // the first valid node at its load cap, should no other be valid
+            let mut capped = None;
+            while let Some(Reverse(fill)) = heap.pop() {
+                if scratch.fits(vnode_id, &fill.name) {
+                    if bound.admits(&fill.name, slots) {
+                        dst = Some(fill);
+                        break;
+                    }
+                    if capped.is_none() {
+                        capped = Some(fill);
+                        continue;
+                    }
+                }
+                skipped.push(Reverse(fill));
+            }
+            if capped.is_some() {
+                overflowed += 1;
+            }
+            let fill = match (dst, capped) {
+                (Some(fill), capped) => {
+                    skipped.extend(capped.map(Reverse));
+                    fill
+                }
+                (None, capped) => capped.ok_or(Error::AntiAffinityUnsatisfiable(vnode_id))?,
+            };
+            heap.extend(skipped);
+            bound.shift(Some(name), &fill.name, slots);
+            let step = self.migration_step(vnode_id, name, &fill.name);
+            scratch.apply_step(&step)?;
+            moves.push(step);
+            heap.push(Reverse(Fill {
+                load: fill.load + 1,
+                ..fill
+            }));
+        }
+        moves.sort_by_key(|m| m.vnode_id);
+
+        let plan = MigrationPlan {
+            added_nodes: Vec::new(),
+            removed_nodes: vec![String::from(name)],
+            steps: moves,
+        };
+        self.apply_plan(&plan)?;
+        self.overflowed = overflowed;
+        self.placed = plan.steps.len();
+        Ok(plan)
+    }
+
+    pub fn show_vnodes(&self) {
+        for vnode in &self.vnodes {
+            if !vnode.slots.is_empty() {
+                println!("vnode id {}: {:?}", vnode.id, vnode.slots);
+            }
+        }
+    }
+
+    pub fn show_nodes(&self) {
+        let mut total_vnodes = 0;
+        let mut total_slots = 0;
+        let slot_copies: usize = self.vnodes.iter().map(|v| v.slots.len()).sum();
+        for (i, name) in self.node_names().into_iter().enumerate() {
+            let node = &self.nodes[name];
+            let mut primary_slots = 0;
+            let mut secondary_slots = 0;
+            let mut non_voting_slots = 0;
+            total_vnodes += node.vnode_set.len();
+            println!(
+                "============= Node {} => name: {}, vnodes count: {}",
+                i + 1,
+                node.name,
+                node.vnode_set.len()
+            );
+            for vnode_id in &node.vnode_set {
+                for slot in &self.vnodes[*vnode_id as usize].slots {
+                    match slot.role {
+                        Role::Primary => primary_slots += 1,
+                        Role::Secondary => secondary_slots += 1,
+                        _ => non_voting_slots += 1,
+                    }
+                }
+            }
+            println!(
+                "Slots: primary {}, secondary {}, non-voting {}",
+                primary_slots, secondary_slots, non_voting_slots
+            );
+            println!(
+                "Share: weight {}, expected {:.2}%, vnodes {:.2}%, slots {:.2}%",
+                node.weight,
+                self.expected_share(name).unwrap_or(0.0) * 100.0,
+                percent(node.vnode_set.len(), self.vnodes.len()),
+                percent(
+                    primary_slots + secondary_slots + non_voting_slots,
+                    slot_copies
+                )
+            );
+            total_slots += primary_slots + secondary_slots + non_voting_slots;
+        }
+
+        println!(
+            "total_vnodes: {}, total_slots {}",
+            total_vnodes, total_slots
+        );
+    }
+}
+
+fn percent(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    part as f64 * 100.0 / total as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_cluster() -> ClusterManager {
+        let mut cm = ClusterManager::with_seed(1);
+        cm.init_vnodes(1024).unwrap();
+        cm.init_slots(32).unwrap();
+        cm.allocate(&["a", "b", "c", "d"]).unwrap();
+        cm
+    }
+
+    #[test]
+    fn test_init_errors() {
+        let mut cm = ClusterManager::new();
+        assert_eq!(cm.init_slots(16), Err(Error::VNodesNotInitialized));
+        assert_eq!(cm.init_vnodes(0), Err(Error::InvalidVNodeNum(0)));
+        cm.init_vnodes(64).unwrap();
+        assert_eq!(cm.init_vnodes(64), Err(Error::VNodesAlreadyInitialized));
+        assert_eq!(cm.init_slots(0), Err(Error::InvalidSlotNum(0)));
+        assert_eq!(
+            cm.allocate(&["a", "b"]),
+            Err(Error::NotEnoughNodes {
+                required: 3,
+                available: 2
+            })
+        );
+        assert_eq!(
+            cm.allocate(&["a", "b", "a"]),
+            Err(Error::DuplicateNode(String::from("a")))
+        );
+    }
+
+    #[test]
+    fn test_init_slots() {
+        let mut cm = ClusterManager::new();
+        cm.init_vnodes(3).unwrap();
+        cm.init_slots(32).unwrap();
+        assert_eq!(cm.slot_num(), 32);
+        let total: usize = cm.vnodes().iter().map(|v| v.slots().len()).sum();
+        assert_eq!(total, 32 * 3);
+        for slot_id in 0..32 {
+            let vnode_ids = cm.slot_vnodes(slot_id).unwrap();
+            assert_eq!(vnode_ids.len(), 3);
+            let primary = cm.vnode(vnode_ids[0]).unwrap();
+            assert!(primary.slots().contains(&Slot {
+                id: slot_id,
+                role: Role::Primary
+            }));
+        }
+        assert_eq!(cm.slot_vnodes(32), Err(Error::SlotNotFound(32)));
+
+        assert_eq!(cm.set_replica_num(4), Err(Error::InvalidReplicaNum(4)));
+        assert_eq!(cm.replica_num(), 3);
+        let mut cm = ClusterManager::new();
+        cm.set_replica_num(5).unwrap();
+        cm.init_vnodes(4).unwrap();
+        assert_eq!(cm.init_slots(8), Err(Error::InvalidReplicaNum(5)));
+    }
+
+    #[test]
+    fn test_replica_num() {
+        let mut cm = ClusterManager::with_seed(3);
+        assert_eq!(cm.set_replica_num(0), Err(Error::InvalidReplicaNum(0)));
+        cm.set_replica_num(5).unwrap();
+        cm.init_vnodes(256).unwrap();
+        cm.init_slots(32).unwrap();
+        assert_eq!(
+            cm.allocate(&["a", "b", "c", "d"]),
+            Err(Error::NotEnoughNodes {
+                required: 5,
+                available: 4
+            })
+        );
+        cm.allocate(&["a", "b", "c", "d", "e"]).unwrap();
+        assert_eq!(cm.slot_replicas(7).unwrap().len(), 5);
+        assert!(cm.validate_placement().is_empty());
+        cm.scale("f").unwrap();
+        assert!(cm.validate_placement().is_empty());
+
+        cm.set_replica_num(2).unwrap();
+        let total: usize = cm.vnodes().iter().map(|v| v.slots().len()).sum();
+        assert_eq!(total, 32 * 2);
+        let replicas = cm.slot_replicas(7).unwrap();
+        assert_eq!(replicas.len(), 2);
+        assert_eq!(replicas[0].role, Role::Primary);
+        assert_eq!(replicas[1].role, Role::Secondary);
+    }
+
+    #[test]
+    fn test_allocate() {
+        let mut cm = new_cluster();
+        let total: usize = cm.nodes().map(|n| n.vnode_set().len()).sum();
+        assert_eq!(total, 1024);
+        for vnode in cm.vnodes() {
+            let owner = vnode.node_name().unwrap();
+            assert!(cm.node(owner).unwrap().vnode_set().contains(&vnode.id()));
+        }
+        // equal weights give every node an equal quota
+        for node in cm.nodes() {
+            assert_eq!(node.vnode_set().len(), 256);
+        }
+        assert!(cm.validate_placement().is_empty());
+        assert_eq!(cm.allocate(&["e"]), Err(Error::AlreadyAllocated));
+    }
+
+    #[test]
+    fn test_scale() {
+        let mut cm = new_cluster();
+        let before: HashMap<String, usize> = cm
+            .nodes()
+            .map(|n| (String::from(n.name()), n.vnode_set().len()))
+            .collect();
+        let moves = cm.scale("e").unwrap().steps;
+        let node = cm.node("e").unwrap();
+        assert_eq!(node.vnode_set().len(), moves.len());
+        assert_eq!(moves.len(), 1024 / 5);
+        let moved: HashSet<u64> = moves.iter().map(|m| m.vnode_id).collect();
+        assert_eq!(moved.len(), moves.len());
+        for m in &moves {
+            assert_eq!(cm.vnode_owner(m.vnode_id).unwrap(), Some("e"));
+            assert!(before.contains_key(&m.from));
+        }
+        let loads: Vec<usize> = cm.nodes().map(|n| n.vnode_set().len()).collect();
+        assert_eq!(loads.iter().sum::<usize>(), 1024);
+        assert!(loads.iter().max().unwrap() - loads.iter().min().unwrap() <= 1);
+        assert_eq!(cm.scale("e"), Err(Error::DuplicateNode(String::from("e"))));
+    }
+
+    #[test]
+    fn test_scale_tolerance() {
+        let mut cm = new_cluster();
+        cm.set_tolerance(10);
+        let plan = cm.scale("e").unwrap();
+        assert_eq!(plan.len(), 1024 / 5 - 10);
+    }
+
+    #[test]
+    fn test_seeded_placement() {
+        let layout = |cm: &ClusterManager| -> Vec<Option<String>> {
+            cm.vnodes()
+                .iter()
+                .map(|v| v.node_name().map(String::from))
+                .collect()
+        };
+        let build = |seed| {
+            let mut cm = ClusterManager::with_seed(seed);
+            cm.init_vnodes(1024).unwrap();
+            cm.init_slots(128).unwrap();
+            cm.allocate(&["d", "c", "b", "a"]).unwrap();
+            cm.scale("e").unwrap();
+            cm
+        };
+        let (cm1, cm2, cm3) = (build(42), build(42), build(43));
+        assert_eq!(cm1.seed(), 42);
+        assert_eq!(layout(&cm1), layout(&cm2));
+        assert_ne!(layout(&cm1), layout(&cm3));
+    }
+
+    #[test]
+    fn test_decommission() {
+        let mut cm = new_cluster();
+        cm.scale("e").unwrap();
+        let leaving = cm.node("b").unwrap().vnode_set().clone();
+        let moves = cm.decommission("b").unwrap().steps;
+        assert!(cm.node("b").is_none());
+        assert_eq!(moves.len(), leaving.len());
+        for m in &moves {
+            assert!(leaving.contains(&m.vnode_id));
+            assert_eq!(m.from, "b");
+            assert_eq!(cm.vnode_owner(m.vnode_id).unwrap(), Some(m.to.as_str()));
+        }
+        let loads: Vec<usize> = cm.nodes().map(|n| n.vnode_set().len()).collect();
+        assert_eq!(loads.iter().sum::<usize>(), 1024);
+        // every receiver was filled up to at most one above the lightest node
+        let min = *loads.iter().min().unwrap();
+        for m in &moves {
+            assert!(cm.node(&m.to).unwrap().vnode_set().len() <= min + 1);
+        }
+
+        assert_eq!(
+            cm.decommission("b"),
+            Err(Error::NodeNotFound(String::from("b")))
+        );
+
+        let mut cm = ClusterManager::with_seed(1);
+        cm.init_vnodes(64).unwrap();
+        cm.allocate(&["a", "b", "c"]).unwrap();
+        assert_eq!(
+            cm.decommission("c"),
+            Err(Error::NotEnoughNodes {
+                required: 3,
+                available: 2
+            })
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use std::error;
+use std::fmt;
+
+/// Errors returned by `ClusterManager` operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// `init_vnodes` was called with zero vnodes.
+    InvalidVNodeNum(u64),
+    /// `init_vnodes` was called on a cluster that already has vnodes.
+    VNodesAlreadyInitialized,
+    /// Slots or nodes were requested before `init_vnodes`.
+    VNodesNotInitialized,
+    /// `init_slots` was called with zero slots.
+    InvalidSlotNum(u64),
+    /// The replication factor must be at least one and at most the vnode
+    /// count.
+    InvalidReplicaNum(u64),
+    /// A replica role layout needs exactly one primary, in first position.
+    InvalidRoles(String),
+    /// Node weights must be at least one.
+    InvalidWeight(u64),
+    /// A load bound must be finite and above zero.
+    InvalidEpsilon(String),
+    /// The operation needs more nodes than the cluster has.
+    NotEnoughNodes { required: usize, available: usize },
+    /// The anti-affinity level needs more failure domains than the cluster has.
+    NotEnoughDomains { required: usize, available: usize },
+    /// No node can take the vnode without breaking replica anti-affinity.
+    AntiAffinityUnsatisfiable(u64),
+    /// `allocate` was called on a cluster that already owns vnodes.
+    AlreadyAllocated,
+    /// A node with this name is already part of the cluster.
+    DuplicateNode(String),
+    /// No node with this name is part of the cluster.
+    NodeNotFound(String),
+    /// A Redis node address is not `host:port`.
+    InvalidAddress(String),
+    /// The vnode id is outside `0..vnode_num`.
+    VNodeNotFound(u64),
+    /// The slot id is outside `0..slot_num`.
+    SlotNotFound(u64),
+    /// A placement strategy was given parameters it cannot work with.
+    InvalidStrategy(String),
+    /// Migration limits need a finite, non-negative slot copy time.
+    InvalidLimits(String),
+    /// A migration plan could not be encoded or decoded.
+    InvalidPlan(String),
+    /// A migration step expects a vnode owner that no longer holds it.
+    StaleStep {
+        vnode_id: u64,
+        expected: String,
+        actual: Option<String>,
+    },
+    /// A node that should have been emptied still owns vnodes.
+    NodeNotEmpty(String),
+    /// A key list could not be read or a line of it is not `name size [hits]`.
+    InvalidKeyspace(String),
+    /// Keys were looked up before `init_slots`.
+    SlotsNotInitialized,
+    /// The vnode has not been allocated to any node yet.
+    VNodeNotAllocated(u64),
+    /// A topology snapshot could not be read, written or decoded, or it
+    /// describes an inconsistent cluster.
+    InvalidSnapshot(String),
+    /// The snapshot was written with a format version this build cannot read.
+    UnsupportedSnapshotVersion(u32),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidVNodeNum(n) => write!(f, "invalid vnode number: {}", n),
+            Error::VNodesAlreadyInitialized => write!(f, "vnodes are already initialized"),
+            Error::VNodesNotInitialized => write!(f, "vnodes are not initialized"),
+            Error::InvalidSlotNum(n) => write!(f, "invalid slot number: {}", n),
+            Error::InvalidReplicaNum(n) => write!(f, "invalid replica number: {}", n),
+            Error::InvalidRoles(msg) => write!(f, "invalid roles: {}", msg),
+            Error::InvalidWeight(n) => write!(f, "invalid weight: {}", n),
+            Error::InvalidEpsilon(epsilon) => write!(f, "invalid load epsilon: {}", epsilon),
+            Error::NotEnoughNodes {
+                required,
+                available,
+            } => write!(
+                f,
+                "not enough nodes: required {}, available {}",
+                required, available
+            ),
+            Error::NotEnoughDomains {
+                required,
+                available,
+            } => write!(
+                f,
+                "not enough failure domains: required {}, available {}",
+                required, available
+            ),
+            Error::AntiAffinityUnsatisfiable(id) => {
+                write!(
+                    f,
+                    "no node can take vnode {} without breaking anti-affinity",
+                    id
+                )
+            }
+            Error::AlreadyAllocated => write!(f, "vnodes are already allocated"),
+            Error::DuplicateNode(name) => write!(f, "node {} already exists", name),
+            Error::NodeNotFound(name) => write!(f, "node {} not found", name),
+            Error::InvalidAddress(addr) => write!(f, "invalid address: {}", addr),
+            Error::VNodeNotFound(id) => write!(f, "vnode {} not found", id),
+            Error::SlotNotFound(id) => write!(f, "slot {} not found", id),
+            Error::InvalidStrategy(msg) => write!(f, "invalid strategy: {}", msg),
+            Error::InvalidLimits(msg) => write!(f, "invalid migration limits: {}", msg),
+            Error::InvalidPlan(msg) => write!(f, "invalid migration plan: {}", msg),
+            Error::StaleStep {
+                vnode_id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "vnode {} is owned by {:?}, expected {}",
+                vnode_id, actual, expected
+            ),
+            Error::NodeNotEmpty(name) => write!(f, "node {} still owns vnodes", name),
+            Error::InvalidKeyspace(msg) => write!(f, "invalid keyspace: {}", msg),
+            Error::SlotsNotInitialized => write!(f, "slots are not initialized"),
+            Error::VNodeNotAllocated(id) => write!(f, "vnode {} is not allocated", id),
+            Error::InvalidSnapshot(msg) => write!(f, "invalid snapshot: {}", msg),
+            Error::UnsupportedSnapshotVersion(v) => {
+                write!(f, "unsupported snapshot version: {}", v)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
@@ -0,0 +1,200 @@
+use crate::cluster::ClusterManager;
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// A hash function whose output depends only on its input bytes, so that
+/// placements survive toolchain upgrades and match other systems.
+pub trait PlacementHasher {
+    fn hash(&self, data: &[u8]) -> u64;
+}
+
+/// CRC16/XMODEM, the checksum Redis Cluster maps keys with.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Crc16;
+
+/// 64-bit xxHash with seed 0.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct XxHash64;
+
+/// 32-bit x86 MurmurHash3 with seed 0.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Murmur3;
+
+/// 64-bit FNV-1a.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fnv1a;
+
+impl PlacementHasher for Crc16 {
+    fn hash(&self, data: &[u8]) -> u64 {
+        let mut crc: u16 = 0;
+        for byte in data {
+            crc ^= (*byte as u16) << 8;
+            for _ in 0..8 {
+                crc = if crc & 0x8000 != 0 {
+                    (crc << 1) ^ 0x1021
+                } else {
+                    crc << 1
+                };
+            }
+        }
+        crc as u64
+    }
+}
+
+impl PlacementHasher for XxHash64 {
+    fn hash(&self, data: &[u8]) -> u64 {
+        xxhash_rust::xxh64::xxh64(data, 0)
+    }
+}
+
+impl PlacementHasher for Murmur3 {
+    fn hash(&self, data: &[u8]) -> u64 {
+        const C1: u32 = 0xcc9e_2d51;
+        const C2: u32 = 0x1b87_3593;
+        let mix = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+
+        let mut h: u32 = 0;
+        let mut chunks = data.chunks_exact(4);
+        for chunk in &mut chunks {
+            let k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            h ^= mix(k);
+            h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+        }
+        let tail = chunks.remainder();
+        if !tail.is_empty() {
+            let k = tail
+                .iter()
+                .rev()
+                .fold(0u32, |k, byte| (k << 8) | *byte as u32);
+            h ^= mix(k);
+        }
+
+        h ^= data.len() as u32;
+        h ^= h >> 16;
+        h = h.wrapping_mul(0x85eb_ca6b);
+        h ^= h >> 13;
+        h = h.wrapping_mul(0xc2b2_ae35);
+        h ^= h >> 16;
+        h as u64
+    }
+}
+
+impl PlacementHasher for Fnv1a {
+    fn hash(&self, data: &[u8]) -> u64 {
+        data.iter().fold(0xcbf2_9ce4_8422_2325, |h, byte| {
+            (h ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+}
+
+/// The hash function a cluster places slots and keys with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashAlgorithm {
+    Crc16,
+    #[default]
+    XxHash64,
+    Murmur3,
+    Fnv1a,
+}
+
+impl HashAlgorithm {
+    pub const ALL: [HashAlgorithm; 4] = [
+        HashAlgorithm::Crc16,
+        HashAlgorithm::XxHash64,
+        HashAlgorithm::Murmur3,
+        HashAlgorithm::Fnv1a,
+    ];
+}
+
+impl PlacementHasher for HashAlgorithm {
+    fn hash(&self, data: &[u8]) -> u64 {
+        match self {
+            HashAlgorithm::Crc16 => Crc16.hash(data),
+            HashAlgorithm::XxHash64 => XxHash64.hash(data),
+            HashAlgorithm::Murmur3 => Murmur3.hash(data),
+            HashAlgorithm::Fnv1a => Fnv1a.hash(data),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            HashAlgorithm::Crc16 => "crc16",
+            HashAlgorithm::XxHash64 => "xxhash64",
+            HashAlgorithm::Murmur3 => "murmur3",
+            HashAlgorithm::Fnv1a => "fnv1a",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        HashAlgorithm::ALL
+            .iter()
+            .find(|algorithm| algorithm.to_string() == s)
+            .cloned()
+            .ok_or_else(|| format!("unknown hash algorithm: {}", s))
+    }
+}
+
+impl ClusterManager {
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
+    }
+
+    /// Selects the hash function for slots and keys.
+    ///
+    /// Slots already placed are placed again with the new function; vnodes
+    /// already allocated keep their owners, so check `validate_placement`.
+    pub fn set_hash_algorithm(&mut self, hash_algorithm: HashAlgorithm) -> Result<()> {
+        self.hash_algorithm = hash_algorithm;
+        if !self.slot_vnodes.is_empty() {
+            self.init_slots(self.max_slot_id)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_values() {
+        assert_eq!(Crc16.hash(b"123456789"), 0x31c3);
+        assert_eq!(Crc16.hash(b"foo") % 16384, 12182);
+        assert_eq!(XxHash64.hash(b""), 0xef46_db37_51d8_e999);
+        assert_eq!(Murmur3.hash(b""), 0);
+        assert_eq!(Murmur3.hash(b"hello"), 0x248b_fa47);
+        assert_eq!(
+            Murmur3.hash(b"The quick brown fox jumps over the lazy dog"),
+            0x2e4f_f723
+        );
+        assert_eq!(Fnv1a.hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(Fnv1a.hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn test_hash_algorithm() {
+        for algorithm in &HashAlgorithm::ALL {
+            assert_eq!(algorithm.to_string().parse(), Ok(*algorithm));
+        }
+        assert!("md5".parse::<HashAlgorithm>().is_err());
+
+        let mut cm = ClusterManager::with_seed(3);
+        cm.init_vnodes(1024).unwrap();
+        cm.init_slots(32).unwrap();
+        let slot_vnodes = cm.slot_vnodes(5).unwrap().to_vec();
+        cm.set_hash_algorithm(HashAlgorithm::Crc16).unwrap();
+        assert_eq!(cm.hash_algorithm(), HashAlgorithm::Crc16);
+        assert_eq!(cm.key_slot(b"foo").unwrap(), 12182 % 32);
+        cm.set_hash_algorithm(HashAlgorithm::XxHash64).unwrap();
+        assert_eq!(cm.slot_vnodes(5).unwrap(), &slot_vnodes[..]);
+    }
+}
@@ -0,0 +1,389 @@
+use crate::cluster::{new_rng, ClusterManager};
+use crate::error::{Error, Result};
+use crate::role::Role;
+use crate::stats::spread;
+use rand::Rng;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// One key of a simulated keyspace.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Key {
+    pub name: String,
+    pub size: u64,
+    /// Requests that read or write the key.
+    pub hits: u64,
+}
+
+/// The keys whose placement a topology change is measured against.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Keyspace {
+    pub keys: Vec<Key>,
+}
+
+impl Keyspace {
+    /// Makes `key_num` keys named `key:<i>` of `size` bytes and spreads
+    /// `requests` evenly at random over them.
+    pub fn uniform(key_num: u64, size: u64, requests: u64, seed: u64) -> Keyspace {
+        Self::sample(key_num, size, requests, seed, |_| 1.0)
+    }
+
+    /// Like `uniform`, but the `i`-th key, counting from one, is requested
+    /// in proportion to `1 / i^exponent`, so a few keys take most requests.
+    pub fn zipfian(key_num: u64, size: u64, exponent: f64, requests: u64, seed: u64) -> Keyspace {
+        Self::sample(key_num, size, requests, seed, |rank| {
+            1.0 / (rank as f64).powf(exponent)
+        })
+    }
+
+    fn sample(
+        key_num: u64,
+        size: u64,
+        requests: u64,
+        seed: u64,
+        weight: impl Fn(u64) -> f64,
+    ) -> Keyspace {
+        let mut cdf = Vec::with_capacity(key_num as usize);
+        let mut total = 0.0;
+        for rank in 1..=key_num {
+            total += weight(rank);
+            cdf.push(total);
+        }
+        let mut hits = vec![0; key_num as usize];
+        let mut rng = new_rng(seed);
+        if let Some(last) = cdf.len().checked_sub(1) {
+            for _ in 0..requests {
+                let u = rng.next_f64() * total;
+                hits[cdf.partition_point(|c| *c <= u).min(last)] += 1;
+            }
+        }
+        Keyspace {
+            keys: hits
+                .into_iter()
+                .enumerate()
+                .map(|(i, hits)| Key {
+                    name: format!("key:{}", i),
+                    size,
+                    hits,
+                })
+                .collect(),
+        }
+    }
+
+    /// Reads one key per line as `name size [hits]`, hits defaulting to 1.
+    /// Blank lines and lines starting with `#` are skipped.
+    pub fn parse(s: &str) -> Result<Keyspace> {
+        let mut keys = Vec::new();
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || Error::InvalidKeyspace(format!("line {}: {}", n + 1, line));
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (name, size, hits) = match fields[..] {
+                [name, size] => (name, size, "1"),
+                [name, size, hits] => (name, size, hits),
+                _ => return Err(invalid()),
+            };
+            keys.push(Key {
+                name: String::from(name),
+                size: size.parse().map_err(|_| invalid())?,
+                hits: hits.parse().map_err(|_| invalid())?,
+            });
+        }
+        Ok(Keyspace { keys })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Keyspace> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .map_err(|e| Error::InvalidKeyspace(format!("{}: {}", path.display(), e)))?;
+        Self::parse(&data)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// What the keys on one node add up to.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KeyLoad {
+    pub name: String,
+    /// Keys the node serves as primary.
+    pub keys: u64,
+    /// Bytes of every replica the node stores.
+    pub bytes: u64,
+    /// Requests the node serves as primary.
+    pub hits: u64,
+}
+
+/// How a keyspace lands on the nodes.
+///
+/// Loads are normalized so that a node serving exactly the mean has load
+/// 1.0; the hot spot is the busiest node's request load.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KeyStats {
+    pub nodes: Vec<KeyLoad>,
+    pub hot_spot: f64,
+    pub hit_load_stddev: f64,
+    pub byte_load_stddev: f64,
+}
+
+/// What a topology change moves of a keyspace.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KeyMovement {
+    pub keys: u64,
+    /// Keys with at least one replica on a new node.
+    pub keys_moved: u64,
+    /// Keys whose primary changed, so whose requests go elsewhere.
+    pub primaries_moved: u64,
+    /// Key replicas copied to a node that did not hold them.
+    pub replicas_moved: u64,
+    pub bytes: u64,
+    pub bytes_moved: u64,
+}
+
+impl ClusterManager {
+    /// Returns the nodes holding every slot, primary first.
+    fn slot_owners(&self) -> Result<Vec<Vec<(String, Role)>>> {
+        (0..self.slot_num())
+            .map(|slot_id| {
+                Ok(self
+                    .slot_replicas(slot_id)?
+                    .into_iter()
+                    .map(|replica| (replica.node_name, replica.role))
+                    .collect())
+            })
+            .collect()
+    }
+
+    /// Maps every key of `keyspace` to its replicas and sums them per node,
+    /// sorted by node name.
+    pub fn key_stats(&self, keyspace: &Keyspace) -> Result<KeyStats> {
+        let owners = self.slot_owners()?;
+        let mut nodes: BTreeMap<&str, KeyLoad> = BTreeMap::new();
+        for name in self.node_names() {
+            nodes.insert(
+                name,
+                KeyLoad {
+                    name: String::from(name),
+                    keys: 0,
+                    bytes: 0,
+                    hits: 0,
+                },
+            );
+        }
+        for key in &keyspace.keys {
+            let slot_id = self.key_slot(key.name.as_bytes())?;
+            for (name, role) in &owners[slot_id as usize] {
+                let load = nodes.get_mut(name.as_str()).unwrap();
+                load.bytes += key.size;
+                if *role == Role::Primary {
+                    load.keys += 1;
+                    load.hits += key.hits;
+                }
+            }
+        }
+
+        let nodes: Vec<KeyLoad> = nodes.into_values().collect();
+        let loads = |value: fn(&KeyLoad) -> u64| -> Vec<f64> {
+            let mean = nodes.iter().map(value).sum::<u64>() as f64 / nodes.len() as f64;
+            nodes
+                .iter()
+                .map(|n| {
+                    if mean > 0.0 {
+                        value(n) as f64 / mean
+                    } else {
+                        1.0
+                    }
+                })
+                .collect()
+        };
+        let hit_loads = loads(|n| n.hits);
+        let hot_spot = hit_loads.iter().cloned().fold(0.0, f64::max);
+        let (hit_load_stddev, _) = spread(&hit_loads);
+        let (byte_load_stddev, _) = spread(&loads(|n| n.bytes));
+        Ok(KeyStats {
+            nodes,
+            hot_spot,
+            hit_load_stddev,
+            byte_load_stddev,
+        })
+    }
+
+    /// Measures what going from this cluster to `after` moves of
+    /// `keyspace`; a key replica moves when a node that did not hold the
+    /// key before holds it after.
+    pub fn key_movement(&self, after: &ClusterManager, keyspace: &Keyspace) -> Result<KeyMovement> {
+        let before_owners = self.slot_owners()?;
+        let after_owners = after.slot_owners()?;
+        let mut movement = KeyMovement {
+            keys: keyspace.len() as u64,
+            keys_moved: 0,
+            primaries_moved: 0,
+            replicas_moved: 0,
+            bytes: 0,
+            bytes_moved: 0,
+        };
+        for key in &keyspace.keys {
+            let before = &before_owners[self.key_slot(key.name.as_bytes())? as usize];
+            let after = &after_owners[after.key_slot(key.name.as_bytes())? as usize];
+            let copied = after
+                .iter()
+                .filter(|(name, _)| before.iter().all(|(other, _)| other != name))
+                .count() as u64;
+            movement.bytes += key.size * after.len() as u64;
+            movement.replicas_moved += copied;
+            movement.bytes_moved += key.size * copied;
+            if copied > 0 {
+                movement.keys_moved += 1;
+            }
+            if before.first().map(|(name, _)| name) != after.first().map(|(name, _)| name) {
+                movement.primaries_moved += 1;
+            }
+        }
+        Ok(movement)
+    }
+}
+
+impl fmt::Display for KeyStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<12} {:>10} {:>14} {:>12}",
+            "node", "keys", "bytes", "hits"
+        )?;
+        for node in &self.nodes {
+            writeln!(
+                f,
+                "{:<12} {:>10} {:>14} {:>12}",
+                node.name, node.keys, node.bytes, node.hits
+            )?;
+        }
+        writeln!(
+            f,
+            "hit load: stddev {:.4}, hot spot {:.4}",
+            self.hit_load_stddev, self.hot_spot
+        )?;
+        writeln!(f, "byte load: stddev {:.4}", self.byte_load_stddev)
+    }
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    part as f64 * 100.0 / total as f64
+}
+
+impl fmt::Display for KeyMovement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "keys moved: {} ({:.2}%), primaries moved: {} ({:.2}%)",
+            self.keys_moved,
+            percent(self.keys_moved, self.keys),
+            self.primaries_moved,
+            percent(self.primaries_moved, self.keys)
+        )?;
+        writeln!(
+            f,
+            "key replicas moved: {}, bytes moved: {} ({:.2}%)",
+            self.replicas_moved,
+            self.bytes_moved,
+            percent(self.bytes_moved, self.bytes)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_cluster() -> ClusterManager {
+        let mut cm = ClusterManager::with_seed(5);
+        cm.init_vnodes(4096).unwrap();
+        cm.init_slots(256).unwrap();
+        cm.allocate(&["a", "b", "c", "d"]).unwrap();
+        cm
+    }
+
+    #[test]
+    fn test_keyspace() {
+        let uniform = Keyspace::uniform(1000, 100, 100_000, 1);
+        assert_eq!(uniform.len(), 1000);
+        assert_eq!(uniform.keys.iter().map(|k| k.hits).sum::<u64>(), 100_000);
+        assert!(uniform.keys.iter().all(|k| k.hits > 30 && k.hits < 200));
+        assert_eq!(uniform, Keyspace::uniform(1000, 100, 100_000, 1));
+
+        let zipfian = Keyspace::zipfian(1000, 100, 1.0, 100_000, 1);
+        assert_eq!(zipfian.keys.iter().map(|k| k.hits).sum::<u64>(), 100_000);
+        assert!(zipfian.keys[0].hits > 10 * zipfian.keys[99].hits);
+
+        let parsed = Keyspace::parse("# name size hits\nfoo 10 3\n\nbar 20\n").unwrap();
+        assert_eq!(
+            parsed.keys,
+            vec![
+                Key {
+                    name: String::from("foo"),
+                    size: 10,
+                    hits: 3
+                },
+                Key {
+                    name: String::from("bar"),
+                    size: 20,
+                    hits: 1
+                }
+            ]
+        );
+        assert_eq!(
+            Keyspace::parse("foo ten"),
+            Err(Error::InvalidKeyspace(String::from("line 1: foo ten")))
+        );
+    }
+
+    #[test]
+    fn test_key_stats() {
+        let cm = new_cluster();
+        let keyspace = Keyspace::uniform(10_000, 100, 100_000, 2);
+        let stats = cm.key_stats(&keyspace).unwrap();
+        assert_eq!(stats.nodes.len(), 4);
+        assert_eq!(stats.nodes.iter().map(|n| n.keys).sum::<u64>(), 10_000);
+        assert_eq!(
+            stats.nodes.iter().map(|n| n.bytes).sum::<u64>(),
+            10_000 * 100 * 3
+        );
+        assert!(stats.hot_spot < 1.3);
+
+        let skewed = cm
+            .key_stats(&Keyspace::zipfian(10_000, 100, 1.2, 100_000, 2))
+            .unwrap();
+        assert!(skewed.hot_spot > stats.hot_spot);
+    }
+
+    #[test]
+    fn test_key_movement() {
+        let cm = new_cluster();
+        let keyspace = Keyspace::uniform(10_000, 100, 0, 3);
+        let mut after = cm.clone();
+        assert_eq!(cm.key_movement(&after, &keyspace).unwrap().bytes_moved, 0);
+
+        let plan = after.scale("e").unwrap();
+        let movement = cm.key_movement(&after, &keyspace).unwrap();
+        assert_eq!(movement.bytes, 10_000 * 100 * 3);
+        assert_eq!(movement.bytes_moved, movement.replicas_moved * 100);
+        assert!(movement.keys_moved >= movement.primaries_moved);
+        let fraction = movement.replicas_moved as f64 / (10_000 * 3) as f64;
+        let slot_fraction = plan.slot_moves() as f64 / (256 * 3) as f64;
+        assert!((fraction - slot_fraction).abs() < 0.05);
+    }
+}
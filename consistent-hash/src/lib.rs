@@ -0,0 +1,38 @@
+mod cluster;
+mod error;
+mod hash;
+mod keyspace;
+mod load;
+mod lookup;
+mod migration;
+mod placement;
+mod redis;
+mod role;
+mod schedule;
+mod snapshot;
+mod stats;
+mod strategy;
+mod weight;
+
+pub use cluster::{ClusterManager, Node, Slot, VNode, DEFAULT_REPLICA_NUM};
+pub use error::{Error, Result};
+pub use hash::{Crc16, Fnv1a, HashAlgorithm, Murmur3, PlacementHasher, XxHash64};
+pub use keyspace::{Key, KeyLoad, KeyMovement, KeyStats, Keyspace};
+pub use lookup::Replica;
+pub use migration::{MigrationPlan, MigrationStep};
+pub use placement::{
+    AntiAffinity, DomainConstraint, DomainLevel, Location, PlacementRule, PlacementViolation,
+};
+pub use redis::{
+    hash_tag, redis_key_slot, RedisCluster, RedisNode, Reshard, REDIS_MIN_MASTERS, REDIS_SLOTS,
+};
+pub use role::Role;
+pub use schedule::{MigrationLimits, MigrationSchedule, Transfer, Wave};
+pub use snapshot::{diff_snapshots, NodeSnapshot, Snapshot, SNAPSHOT_VERSION};
+pub use stats::{BalanceStats, DomainStats, MovementStats, NodeStats};
+pub use strategy::{
+    all_strategies, compare_strategy, BoundedLoadStrategy, JumpStrategy, MaglevStrategy,
+    PlacementStrategy, RendezvousStrategy, RingStrategy, RoundRobinStrategy, StrategyReport,
+    VNodeStrategy,
+};
+pub use weight::DEFAULT_WEIGHT;
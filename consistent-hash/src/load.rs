@@ -0,0 +1,173 @@
+use crate::cluster::ClusterManager;
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+
+/// Accepts a load bound only if it is finite and above zero.
+pub(crate) fn check_epsilon(epsilon: f64) -> Result<()> {
+    if epsilon.is_finite() && epsilon > 0.0 {
+        Ok(())
+    } else {
+        Err(Error::InvalidEpsilon(epsilon.to_string()))
+    }
+}
+
+/// Caps and slot replica loads of every node, worked out once for a
+/// placement and kept current as it assigns vnodes.
+pub(crate) struct LoadBound {
+    // `(cap, load)` per node, `None` without a cap
+    nodes: Option<HashMap<String, (f64, usize)>>,
+}
+
+impl LoadBound {
+    /// Whether `name` stays within its cap after taking `slots` replicas.
+    pub(crate) fn admits(&self, name: &str, slots: usize) -> bool {
+        match &self.nodes {
+            Some(nodes) => nodes
+                .get(name)
+                .is_some_and(|(cap, load)| (load + slots) as f64 <= *cap),
+            None => true,
+        }
+    }
+
+    /// Records `slots` replicas moving from `from`, if any, to `to`.
+    pub(crate) fn shift(&mut self, from: Option<&str>, to: &str, slots: usize) {
+        if let Some(nodes) = &mut self.nodes {
+            if let Some((_, load)) = from.and_then(|from| nodes.get_mut(from)) {
+                *load -= slots;
+            }
+            if let Some((_, load)) = nodes.get_mut(to) {
+                *load += slots;
+            }
+        }
+    }
+}
+
+impl ClusterManager {
+    pub fn load_epsilon(&self) -> Option<f64> {
+        self.load_epsilon
+    }
+
+    /// Caps every node at `1 + epsilon` times its weighted share of the slot
+    /// replicas, as in consistent hashing with bounded loads: `allocate`,
+    /// `scale` and `decommission` pass a vnode on to the next valid node when
+    /// it would take its owner over the cap. `None` lifts the cap.
+    ///
+    /// Vnodes already placed stay where they are.
+    pub fn set_load_epsilon(&mut self, epsilon: Option<f64>) -> Result<()> {
+        if let Some(epsilon) = epsilon {
+            check_epsilon(epsilon)?;
+        }
+        self.load_epsilon = epsilon;
+        Ok(())
+    }
+
+    /// Slot replicas held by the vnodes of `name`.
+    pub fn slot_load(&self, name: &str) -> Option<usize> {
+        let node = self.nodes.get(name)?;
+        Some(
+            node.vnode_set
+                .iter()
+                .map(|v| self.vnodes[*v as usize].slots.len())
+                .sum(),
+        )
+    }
+
+    /// Fraction of the vnodes placed by the last `allocate`, `scale` or
+    /// `decommission` that passed over a node at its load cap, or `None`
+    /// without a cap.
+    pub fn overflow_fraction(&self) -> Option<f64> {
+        self.load_epsilon?;
+        if self.placed == 0 {
+            return Some(0.0);
+        }
+        Some(self.overflowed as f64 / self.placed as f64)
+    }
+
+    /// The load bound of every node as it stands, with shares taken out of
+    /// `total_weight`.
+    pub(crate) fn load_bound(&self, total_weight: u64) -> LoadBound {
+        let epsilon = match self.load_epsilon {
+            Some(epsilon) => epsilon,
+            None => return LoadBound { nodes: None },
+        };
+        let mut loads: HashMap<&str, usize> = HashMap::new();
+        let mut total = 0;
+        for vnode in &self.vnodes {
+            total += vnode.slots.len();
+            if let Some(name) = vnode.node_name.as_deref() {
+                *loads.entry(name).or_default() += vnode.slots.len();
+            }
+        }
+        let nodes = self
+            .nodes
+            .values()
+            .map(|node| {
+                let share = node.weight as f64 / total_weight as f64;
+                let cap = ((1.0 + epsilon) * total as f64 * share).ceil();
+                let load = loads.get(node.name.as_str()).cloned().unwrap_or(0);
+                (node.name.clone(), (cap, load))
+            })
+            .collect();
+        LoadBound { nodes: Some(nodes) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_cluster(epsilon: Option<f64>) -> ClusterManager {
+        let mut cm = ClusterManager::with_seed(1);
+        cm.set_load_epsilon(epsilon).unwrap();
+        cm.init_vnodes(256).unwrap();
+        cm.init_slots(128).unwrap();
+        cm.allocate(&["a", "b", "c", "d", "e", "f"]).unwrap();
+        cm
+    }
+
+    fn max_load(cm: &ClusterManager) -> usize {
+        cm.node_names()
+            .iter()
+            .map(|name| cm.slot_load(name).unwrap())
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_load_epsilon() {
+        let mut cm = ClusterManager::with_seed(5);
+        for epsilon in [0.0, -0.5, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                cm.set_load_epsilon(Some(epsilon)),
+                Err(Error::InvalidEpsilon(_))
+            ));
+        }
+        assert_eq!(cm.load_epsilon(), None);
+        assert_eq!(cm.overflow_fraction(), None);
+        cm.set_load_epsilon(Some(0.1)).unwrap();
+        assert_eq!(cm.load_epsilon(), Some(0.1));
+        assert_eq!(cm.overflow_fraction(), Some(0.0));
+    }
+
+    #[test]
+    fn test_bounded_allocate() {
+        // 384 replicas over 6 nodes, 64 on average
+        let free = new_cluster(None);
+        let bounded = new_cluster(Some(0.1));
+        assert!(max_load(&free) > 71);
+        assert!(max_load(&bounded) <= 71);
+        assert!(bounded.overflow_fraction().unwrap() > 0.0);
+        assert!(bounded.validate_placement().is_empty());
+    }
+
+    #[test]
+    fn test_bounded_scale() {
+        let mut cm = new_cluster(Some(0.1));
+        cm.scale("g").unwrap();
+        // 384 replicas over 7 nodes, about 55 on average
+        assert!(cm.slot_load("g").unwrap() <= 61);
+        cm.decommission("a").unwrap();
+        assert!(max_load(&cm) <= 71);
+        assert!(cm.validate_placement().is_empty());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::cluster::{ClusterManager, Slot};
+use crate::error::{Error, Result};
+use crate::hash::PlacementHasher;
+use crate::role::Role;
+
+/// One copy of a slot and the node currently serving it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replica {
+    pub slot_id: u64,
+    pub vnode_id: u64,
+    pub role: Role,
+    pub node_name: String,
+}
+
+impl ClusterManager {
+    /// Maps an arbitrary key to the slot that stores it.
+    pub fn key_slot(&self, key: &[u8]) -> Result<u64> {
+        if self.slot_vnodes.is_empty() {
+            return Err(Error::SlotsNotInitialized);
+        }
+        Ok(self.hash_algorithm.hash(key) % self.slot_vnodes.len() as u64)
+    }
+
+    /// Returns the replicas of `slot_id`, primary first.
+    pub fn slot_replicas(&self, slot_id: u64) -> Result<Vec<Replica>> {
+        let mut replicas = Vec::new();
+        for (r, vnode_id) in self.slot_vnodes(slot_id)?.iter().enumerate() {
+            let vnode = self.vnode(*vnode_id)?;
+            let node_name = vnode
+                .node_name()
+                .ok_or(Error::VNodeNotAllocated(*vnode_id))?;
+            let role = self.roles[r];
+            if vnode.slots().contains(&Slot { id: slot_id, role }) {
+                replicas.push(Replica {
+                    slot_id,
+                    vnode_id: *vnode_id,
+                    role,
+                    node_name: String::from(node_name),
+                });
+            }
+        }
+        Ok(replicas)
+    }
+
+    /// Returns the replicas owning `key`, primary first.
+    pub fn lookup(&self, key: &[u8]) -> Result<Vec<Replica>> {
+        let slot_id = self.key_slot(key)?;
+        self.slot_replicas(slot_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let mut cm = ClusterManager::new();
+        cm.init_vnodes(256).unwrap();
+        assert_eq!(cm.lookup(b"foo"), Err(Error::SlotsNotInitialized));
+        cm.init_slots(64).unwrap();
+        assert_eq!(
+            cm.lookup(b"foo"),
+            Err(Error::VNodeNotAllocated(
+                cm.slot_vnodes(cm.key_slot(b"foo").unwrap()).unwrap()[0]
+            ))
+        );
+        cm.allocate(&["a", "b", "c", "d"]).unwrap();
+
+        for key in &["foo", "bar", "baz", "user:1000"] {
+            let replicas = cm.lookup(key.as_bytes()).unwrap();
+            assert_eq!(replicas.len(), 3);
+            assert_eq!(replicas[0].role, Role::Primary);
+            assert!(replicas[1..].iter().all(|r| r.role == Role::Secondary));
+            assert!(replicas.iter().all(|r| r.slot_id == replicas[0].slot_id));
+            for replica in &replicas {
+                let node = cm.node(&replica.node_name).unwrap();
+                assert!(node.vnode_set().contains(&replica.vnode_id));
+            }
+        }
+        assert_eq!(cm.lookup(b"foo"), cm.lookup(b"foo"));
+    }
+}
@@ -1,254 +1,811 @@
-use rand::Rng;
-use std::collections::hash_map::DefaultHasher;
-use std::collections::{HashMap, HashSet};
-use std::hash::{Hash, Hasher};
-use std::ops::Deref;
-
-#[derive(Debug)]
-enum Role {
-    PRIMARY,
-    SECONDARY,
-}
+use clap::{Arg, ArgAction, ArgMatches};
+use consistent_hash::{
+    all_strategies, compare_strategy, redis_key_slot, ClusterManager, DomainLevel, DomainStats,
+    Keyspace, Location, MigrationLimits, MigrationPlan, PlacementRule, RedisCluster, Role,
+    Snapshot, StrategyReport, DEFAULT_WEIGHT,
+};
+use std::path::Path;
 
-#[derive(Debug)]
-struct Slot {
-    id: u64,
-    role: Role,
+fn schedule_args() -> [Arg; 5] {
+    [
+        Arg::new("schedule")
+            .help("Also prints the moves in waves that keep within the copy limits")
+            .long("schedule")
+            .action(ArgAction::SetTrue),
+        Arg::new("max-per-source")
+            .help("Sets the copies one node sends at once, 0 for no limit")
+            .long("max-per-source")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("2")
+            .action(ArgAction::Set),
+        Arg::new("max-per-target")
+            .help("Sets the copies one node receives at once, 0 for no limit")
+            .long("max-per-target")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("2")
+            .action(ArgAction::Set),
+        Arg::new("max-concurrent")
+            .help("Sets the copies running at once cluster-wide, 0 for no limit")
+            .long("max-concurrent")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("16")
+            .action(ArgAction::Set),
+        Arg::new("slot-copy-secs")
+            .help("Sets the seconds one slot replica takes to copy")
+            .long("slot-copy-secs")
+            .value_parser(clap::value_parser!(f64))
+            .default_value("1.0")
+            .action(ArgAction::Set),
+    ]
 }
 
-#[derive(Debug)]
-struct VNode {
-    id: u64,
-    node_name: Option<String>,
-    slots: Vec<Slot>,
+fn app_args() -> ArgMatches {
+    clap::Command::new("consistent-hash")
+        .about("Plans vnode and slot placement on a cluster stored in a topology file")
+        .subcommand_required(true)
+        .arg(
+            Arg::new("topology")
+                .help("Sets the topology file, TOML if it ends in .toml, JSON otherwise")
+                .long("topology")
+                .short('t')
+                .default_value("topology.json")
+                .global(true)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .help("Prints the result without writing the topology file")
+                .long("dry-run")
+                .short('n')
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("json")
+                .help("Prints statistics as JSON instead of tables")
+                .long("json")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .subcommand(
+            clap::Command::new("init")
+                .about("Creates an empty cluster with vnodes and slots")
+                .arg(
+                    Arg::new("vnodes")
+                        .help("Sets the vnode count")
+                        .long("vnodes")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("65536")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("slots")
+                        .help("Sets the slot count")
+                        .long("slots")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("128")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("replicas")
+                        .help("Sets the copies kept for every slot, primary included")
+                        .long("replicas")
+                        .short('r')
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("3")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("roles")
+                        .help("Sets the role of every replica, e.g. primary,secondary,learner")
+                        .long("roles")
+                        .value_delimiter(',')
+                        .value_parser(["primary", "secondary", "learner", "witness", "arbiter"])
+                        .conflicts_with("replicas")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("seed")
+                        .help("Sets the placement seed (random if omitted)")
+                        .long("seed")
+                        .value_parser(clap::value_parser!(u64))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("tolerance")
+                        .help("Sets how many vnodes a scaled-in node may fall short of its share")
+                        .long("tolerance")
+                        .value_parser(clap::value_parser!(u64))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("load-epsilon")
+                        .help("Caps every node at 1 + this times its share of the slot replicas")
+                        .long("load-epsilon")
+                        .value_parser(clap::value_parser!(f64))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("anti-affinity")
+                        .help("Sets the failure domain replicas must not share")
+                        .long("anti-affinity")
+                        .value_parser(["node", "host", "rack", "zone", "region"])
+                        .default_value("node")
+                        .conflicts_with("rule")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("rule")
+                        .help("Sets the replicas allowed per domain, e.g. zone=1,host=1")
+                        .long("rule")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("hash")
+                        .help("Sets the hash function placing slots and keys")
+                        .long("hash")
+                        .value_parser(["crc16", "xxhash64", "murmur3", "fnv1a"])
+                        .default_value("xxhash64")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("force")
+                        .help("Overwrites an existing topology file")
+                        .long("force")
+                        .short('f')
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("allocate")
+                .about("Adds nodes and spreads all vnodes over the cluster")
+                .arg(
+                    Arg::new("names")
+                        .help("Sets the nodes to add")
+                        .num_args(0..)
+                        .action(ArgAction::Append),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("add-node")
+                .about("Adds a node, moving vnodes to it once the cluster is allocated")
+                .arg(Arg::new("name").help("Sets the node name").required(true))
+                .arg(
+                    Arg::new("weight")
+                        .help("Sets the node weight")
+                        .long("weight")
+                        .short('w')
+                        .value_parser(clap::value_parser!(u64))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("region")
+                        .help("Sets the node region")
+                        .long("region")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("zone")
+                        .help("Sets the node zone")
+                        .long("zone")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("rack")
+                        .help("Sets the node rack")
+                        .long("rack")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("host")
+                        .help("Sets the node host")
+                        .long("host")
+                        .action(ArgAction::Set),
+                )
+                .args(schedule_args()),
+        )
+        .subcommand(
+            clap::Command::new("remove-node")
+                .about("Removes a node, handing its vnodes to the others")
+                .arg(Arg::new("name").help("Sets the node name").required(true))
+                .args(schedule_args()),
+        )
+        .subcommand(
+            clap::Command::new("show")
+                .about("Prints the nodes and their shares")
+                .arg(
+                    Arg::new("vnodes")
+                        .help("Also prints the slots of every vnode")
+                        .long("vnodes")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("stats")
+                .about("Prints per-node load and how evenly it is spread")
+                .arg(
+                    Arg::new("level")
+                        .help("Sums the load per domain at this level instead")
+                        .long("level")
+                        .value_parser(["host", "rack", "zone", "region"])
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("compare")
+                .about("Runs every placement strategy over the same membership change")
+                .arg(
+                    Arg::new("before")
+                        .help("Sets the nodes before the change, as name[:weight]")
+                        .long("before")
+                        .value_delimiter(',')
+                        .required(true)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("after")
+                        .help("Sets the nodes after the change, as name[:weight]")
+                        .long("after")
+                        .value_delimiter(',')
+                        .required(true)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("vnodes")
+                        .help("Sets the vnode count of the vnode strategy")
+                        .long("vnodes")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("65536")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("slots")
+                        .help("Sets the slot count")
+                        .long("slots")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("1024")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("replicas")
+                        .help("Sets the copies kept for every slot, primary included")
+                        .long("replicas")
+                        .short('r')
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("3")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("seed")
+                        .help("Sets the seed of the vnode strategy")
+                        .long("seed")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("0")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("epsilon")
+                        .help("Sets how far above the average the bounded-load ring lets a node go")
+                        .long("epsilon")
+                        .value_parser(clap::value_parser!(f64))
+                        .default_value("0.25")
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("redis-cluster")
+                .about("Lays out a Redis Cluster as redis-cli --cluster create would and plans reshards")
+                .arg(
+                    Arg::new("nodes")
+                        .help("Sets the cluster nodes as host:port or redis:// URLs, masters first")
+                        .long("nodes")
+                        .value_delimiter(',')
+                        .required(true)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("cluster-replicas")
+                        .help("Sets the replicas of every master")
+                        .long("cluster-replicas")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("0")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("add-master")
+                        .help("Adds empty masters and reshards slots to them")
+                        .long("add-master")
+                        .value_delimiter(',')
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("remove-master")
+                        .help("Reshards the slots of masters away and removes them")
+                        .long("remove-master")
+                        .value_delimiter(',')
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("format")
+                        .help("Prints the final layout as CLUSTER NODES or CLUSTER SLOTS replies")
+                        .long("format")
+                        .value_parser(["nodes", "slots"])
+                        .default_value("nodes")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("keys")
+                        .help("Sets keys to print the slot and master of")
+                        .long("keys")
+                        .num_args(1..)
+                        .action(ArgAction::Append),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("simulate")
+                .about("Maps a keyspace onto the cluster and measures what a node change moves")
+                .arg(
+                    Arg::new("uniform")
+                        .help("Generates this many keys requested evenly")
+                        .long("uniform")
+                        .value_parser(clap::value_parser!(u64))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("zipf")
+                        .help("Generates this many keys with Zipfian request counts")
+                        .long("zipf")
+                        .value_parser(clap::value_parser!(u64))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("keys-file")
+                        .help("Reads the keys from a file of name size [hits] lines")
+                        .long("keys-file")
+                        .action(ArgAction::Set),
+                )
+                .group(
+                    clap::ArgGroup::new("keyspace")
+                        .args(["uniform", "zipf", "keys-file"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("exponent")
+                        .help("Sets the Zipfian exponent")
+                        .long("exponent")
+                        .value_parser(clap::value_parser!(f64))
+                        .default_value("0.99")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("value-size")
+                        .help("Sets the size of generated keys in bytes")
+                        .long("value-size")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("1024")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("requests")
+                        .help("Sets the requests spread over generated keys")
+                        .long("requests")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("1000000")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("seed")
+                        .help("Sets the seed of generated keyspaces")
+                        .long("seed")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("0")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("add-node")
+                        .help("Measures adding this node")
+                        .long("add-node")
+                        .conflicts_with("remove-node")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("remove-node")
+                        .help("Measures removing this node")
+                        .long("remove-node")
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("lookup")
+                .about("Prints the slot and replicas serving each key")
+                .arg(
+                    Arg::new("keys")
+                        .help("Sets the keys to look up")
+                        .required(true)
+                        .num_args(1..)
+                        .action(ArgAction::Append),
+                ),
+        )
+        .get_matches()
 }
 
-#[derive(Debug)]
-struct Node {
-    name: String,
-    vnode_set: HashSet<u64>,
+fn is_allocated(cm: &ClusterManager) -> bool {
+    cm.vnodes().iter().any(|vnode| vnode.node_name().is_some())
 }
 
-#[derive(Debug)]
-struct ClusterManager {
-    max_vnode_id: u64,
-    max_slot_id: u64,
-    nodes: HashMap<String, Node>,
-    vnodes: Vec<VNode>,
+fn migration_limits(matches: &ArgMatches) -> consistent_hash::Result<Option<MigrationLimits>> {
+    if !matches.get_flag("schedule") {
+        return Ok(None);
+    }
+    MigrationLimits::new(
+        *matches.get_one::<usize>("max-per-source").unwrap(),
+        *matches.get_one::<usize>("max-per-target").unwrap(),
+        *matches.get_one::<usize>("max-concurrent").unwrap(),
+        *matches.get_one::<f64>("slot-copy-secs").unwrap(),
+    )
+    .map(Some)
 }
 
-impl VNode {
-    fn new(id: u64) -> VNode {
-        VNode {
-            id: id,
-            node_name: None,
-            slots: Vec::new(),
-        }
+fn print_plan(
+    cm: &ClusterManager,
+    action: &str,
+    plan: &MigrationPlan,
+    limits: Option<&MigrationLimits>,
+    json: bool,
+) {
+    let stats = cm.movement_stats(plan);
+    let schedule = limits.map(|limits| plan.schedule(limits));
+    if json {
+        let report = match schedule {
+            Some(schedule) => serde_json::json!({ "movement": stats, "schedule": schedule }),
+            None => serde_json::to_value(&stats).unwrap(),
+        };
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        return;
     }
-
-    fn assign(&mut self, node_name: Option<String>) {
-        self.node_name = node_name;
+    print!("{}", plan);
+    println!("{} =>", action);
+    print!("{}", stats);
+    print_overflow(cm);
+    if let Some(schedule) = schedule {
+        print!("{}", schedule);
     }
 }
 
-impl Node {
-    fn new(name: &str) -> Node {
-        Node {
-            name: String::from(name),
-            vnode_set: HashSet::new(),
-        }
+fn print_overflow(cm: &ClusterManager) {
+    if let Some(fraction) = cm.overflow_fraction() {
+        println!("vnodes passed over a capped node: {:.2}%", fraction * 100.0);
     }
+}
 
-    fn pickup_vnode(&mut self, vnode_id: u64) {
-        self.vnode_set.insert(vnode_id);
+fn init(matches: &ArgMatches, path: &Path) -> Result<ClusterManager, Box<dyn std::error::Error>> {
+    if path.exists() && !matches.get_flag("force") {
+        return Err(format!(
+            "{} already exists, use --force to overwrite",
+            path.display()
+        )
+        .into());
     }
-
-    fn drop_vnode(&mut self, vnode_id: u64) {
-        self.vnode_set.remove(&vnode_id);
+    let mut cm = match matches.get_one::<u64>("seed") {
+        Some(seed) => ClusterManager::with_seed(*seed),
+        None => ClusterManager::new(),
+    };
+    if let Some(tolerance) = matches.get_one::<u64>("tolerance") {
+        cm.set_tolerance(*tolerance);
     }
-}
-
-impl ClusterManager {
-    fn new() -> ClusterManager {
-        ClusterManager {
-            max_vnode_id: 1024,
-            max_slot_id: 16,
-            nodes: HashMap::new(),
-            vnodes: Vec::new(),
+    cm.set_load_epsilon(matches.get_one::<f64>("load-epsilon").copied())?;
+    match matches.get_one::<String>("rule") {
+        Some(rule) => cm.set_placement_rule(rule.parse::<PlacementRule>()?),
+        None => cm.set_anti_affinity(
+            matches
+                .get_one::<String>("anti-affinity")
+                .unwrap()
+                .parse::<DomainLevel>()?,
+        ),
+    }
+    cm.set_hash_algorithm(matches.get_one::<String>("hash").unwrap().parse()?)?;
+    match matches.get_many::<String>("roles") {
+        Some(roles) => {
+            let roles = roles
+                .map(|role| role.parse())
+                .collect::<Result<Vec<Role>, String>>()?;
+            cm.set_roles(&roles)?;
         }
+        None => cm.set_replica_num(*matches.get_one::<u64>("replicas").unwrap())?,
     }
+    cm.init_vnodes(*matches.get_one::<u64>("vnodes").unwrap())?;
+    cm.init_slots(*matches.get_one::<u64>("slots").unwrap())?;
+    println!(
+        "init => seed {}, {} vnodes, {} slots, replica num {}",
+        cm.seed(),
+        cm.vnode_num(),
+        cm.slot_num(),
+        cm.replica_num()
+    );
+    Ok(cm)
+}
 
-    fn init_vnodes(&mut self, vnode_num: u64) {
-        self.max_vnode_id = vnode_num;
-        for vnode_id in (0..self.max_vnode_id) {
-            let vnode = VNode::new(vnode_id);
-            self.vnodes.push(vnode);
+fn parse_nodes(matches: &ArgMatches, id: &str) -> Result<Vec<(String, u64)>, String> {
+    matches
+        .get_many::<String>(id)
+        .unwrap()
+        .map(|node| match node.split_once(':') {
+            Some((name, weight)) => weight
+                .parse()
+                .map(|weight| (String::from(name), weight))
+                .map_err(|_| format!("invalid weight in {}", node)),
+            None => Ok((node.clone(), DEFAULT_WEIGHT)),
+        })
+        .collect()
+}
+
+fn compare(matches: &ArgMatches, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let before = parse_nodes(matches, "before")?;
+    let after = parse_nodes(matches, "after")?;
+    let slot_num = *matches.get_one::<u64>("slots").unwrap();
+    let replica_num = *matches.get_one::<u64>("replicas").unwrap();
+    let strategies = all_strategies(
+        *matches.get_one::<u64>("vnodes").unwrap(),
+        slot_num,
+        replica_num,
+        *matches.get_one::<u64>("seed").unwrap(),
+        *matches.get_one::<f64>("epsilon").unwrap(),
+    )?;
+
+    let mut reports = Vec::new();
+    for mut strategy in strategies {
+        reports.push(compare_strategy(
+            &mut *strategy,
+            &before,
+            &after,
+            slot_num,
+            replica_num as usize,
+        )?);
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    } else {
+        println!("{}", StrategyReport::header());
+        for report in &reports {
+            print!("{}", report);
         }
     }
+    Ok(())
+}
 
-    fn init_slots(&mut self, slot_num: u64) -> bool {
-        if self.vnodes.len() <= 0 {
-            return false;
-        }
-        self.max_slot_id = slot_num;
-
-        let mut dh = DefaultHasher::new();
-
-        for slot_id in (0..self.max_slot_id) {
-            slot_id.hash(&mut dh);
-            //let vnode_id = dh.finish() % self.max_vnode_id;
-            let hc = dh.finish() % 2u64.pow(32);
-            let vnode_id = hc / (2u64.pow(32) / self.max_vnode_id);
-
-            let slot = Slot {
-                id: slot_id,
-                role: Role::PRIMARY,
-            };
-            self.vnodes[vnode_id as usize].slots.push(slot);
-            let slot = Slot {
-                id: slot_id,
-                role: Role::SECONDARY,
-            };
-            let vnode_id = (vnode_id + 1) % self.max_vnode_id;
-            self.vnodes[vnode_id as usize].slots.push(slot);
-            let slot = Slot {
-                id: slot_id,
-                role: Role::SECONDARY,
-            };
-            let vnode_id = (vnode_id + 1) % self.max_vnode_id;
-            self.vnodes[vnode_id as usize].slots.push(slot);
+fn simulate(
+    cm: &ClusterManager,
+    matches: &ArgMatches,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let size = *matches.get_one::<u64>("value-size").unwrap();
+    let requests = *matches.get_one::<u64>("requests").unwrap();
+    let seed = *matches.get_one::<u64>("seed").unwrap();
+    let keyspace = if let Some(key_num) = matches.get_one::<u64>("uniform") {
+        Keyspace::uniform(*key_num, size, requests, seed)
+    } else if let Some(key_num) = matches.get_one::<u64>("zipf") {
+        let exponent = *matches.get_one::<f64>("exponent").unwrap();
+        Keyspace::zipfian(*key_num, size, exponent, requests, seed)
+    } else {
+        Keyspace::load(matches.get_one::<String>("keys-file").unwrap())?
+    };
+
+    let before = cm.key_stats(&keyspace)?;
+    let mut after = cm.clone();
+    let action = if let Some(name) = matches.get_one::<String>("add-node") {
+        after.scale(name)?;
+        format!("add node {}", name)
+    } else if let Some(name) = matches.get_one::<String>("remove-node") {
+        after.decommission(name)?;
+        format!("remove node {}", name)
+    } else {
+        if json {
+            println!("{}", serde_json::to_string_pretty(&before)?);
+        } else {
+            print!("{}", before);
         }
-        return true;
+        return Ok(());
+    };
+    let movement = cm.key_movement(&after, &keyspace)?;
+    let after = after.key_stats(&keyspace)?;
+    if json {
+        let report = serde_json::json!({
+            "before": before,
+            "movement": movement,
+            "after": after,
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", before);
+        println!("{} =>", action);
+        print!("{}", movement);
+        print!("{}", after);
     }
+    Ok(())
+}
 
-    fn add_nodes(&mut self, names: &[&str]) {
-        for name in names {
-            self.nodes.insert(String::from(*name), Node::new(*name));
+fn redis_cluster(matches: &ArgMatches) -> consistent_hash::Result<()> {
+    let nodes: Vec<&str> = matches
+        .get_many::<String>("nodes")
+        .unwrap()
+        .map(String::as_str)
+        .collect();
+    let replicas = *matches.get_one::<usize>("cluster-replicas").unwrap();
+    let mut cluster = RedisCluster::create(&nodes, replicas)?;
+    println!(
+        "create => masters {}, replicas {}",
+        cluster.masters().len(),
+        cluster.nodes().len() - cluster.masters().len()
+    );
+
+    let mut reshards = Vec::new();
+    for addr in matches.get_many::<String>("add-master").unwrap_or_default() {
+        reshards.extend(cluster.add_master(addr)?);
+    }
+    for addr in matches
+        .get_many::<String>("remove-master")
+        .unwrap_or_default()
+    {
+        reshards.extend(cluster.remove_master(addr)?);
+    }
+    for reshard in &reshards {
+        print!("{}", reshard);
+        for command in reshard.commands() {
+            println!("  {}", command);
         }
     }
+    if !reshards.is_empty() {
+        let moved: usize = reshards.iter().map(|reshard| reshard.slots.len()).sum();
+        println!("reshard => moved slots: {}", moved);
+    }
 
-    pub fn allocate(&mut self, names: &[&str]) {
-        self.add_nodes(names);
-        let mut rnd = rand::thread_rng();
-        let mut ns: Vec<String> = self.nodes.keys().cloned().collect();
-
-        for i in (0..self.max_vnode_id) {
-            let ri = rnd.gen_range(0, ns.len() - 2) as usize;
-            let key = ns.remove(ri);
-            let name = key.clone();
-            ns.push(key);
-            if let Some(node) = self.nodes.get_mut(&name) {
-                //println!("node {} pick up vnode {} ==> ns {:?}", index, vnode_id, ns);
-                node.pickup_vnode(i);
-                self.vnodes[i as usize].node_name = Some(name);
-            }
+    match matches.get_one::<String>("format").unwrap().as_str() {
+        "slots" => print!("{}", cluster.cluster_slots()),
+        _ => print!("{}", cluster.cluster_nodes()),
+    }
+    for key in matches.get_many::<String>("keys").unwrap_or_default() {
+        let slot_id = redis_key_slot(key.as_bytes());
+        if let Some(master) = cluster.slot_master(slot_id) {
+            println!("{} => slot {} on {}", key, slot_id, master.addr());
         }
     }
+    Ok(())
+}
 
-    pub fn scale(&mut self, name: &str) {
-        let old_nodes_num = self.nodes.len() as u64;
-        self.add_nodes(&[name]);
-        let new_nodes_num = self.nodes.len() as u64;
-        let vnodes_num_per_node = self.max_vnode_id / new_nodes_num;
-
-        let mut rnd = rand::thread_rng();
-        let vnodes_num = self.max_vnode_id;
-        let mut indexes = Vec::new();
-
-        // select vnodes_num_per_node vnodes and remove old node name
-        for i in (0..vnodes_num_per_node) {
-            let index = rnd.gen_range(0, vnodes_num) as usize;
-            let node_name_src = self.vnodes[index]
-                .node_name
-                .as_ref()
-                .map_or("", String::deref);
-            if node_name_src.len() > 0 {
-                if let Some(node_src) = self.nodes.get_mut(node_name_src) {
-                    node_src.drop_vnode(index as u64);
-                }
-            }
-            indexes.push(index);
-        }
+fn add_node(
+    cm: &mut ClusterManager,
+    matches: &ArgMatches,
+    json: bool,
+) -> consistent_hash::Result<()> {
+    let name = matches.get_one::<String>("name").unwrap();
+    let weight = *matches.get_one::<u64>("weight").unwrap_or(&DEFAULT_WEIGHT);
+    let limits = migration_limits(matches)?;
+    let location = Location {
+        region: matches.get_one::<String>("region").cloned(),
+        zone: matches.get_one::<String>("zone").cloned(),
+        rack: matches.get_one::<String>("rack").cloned(),
+        host: matches.get_one::<String>("host").cloned(),
+    };
 
-        println!(
-            "scale => vnodes_num_per_node: {}, indexes: {}",
-            vnodes_num_per_node,
-            indexes.len()
+    if is_allocated(cm) {
+        let plan = cm.scale_weighted_at(name, weight, location)?;
+        print_plan(
+            cm,
+            &format!("add node {}", name),
+            &plan,
+            limits.as_ref(),
+            json,
         );
-        // reassign vnodes_num_per_node vnodes to the new node
-        if let Some(node_dst) = self.nodes.get_mut(&String::from(name)) {
-            for index in &indexes {
-                node_dst.pickup_vnode(*index as u64);
-                //println!("scale: pickup vnode {}", index);
-                self.vnodes[*index].node_name = Some(String::from(name));
-            }
-            println!(
-                "scale => dst node vnode set size: {}",
-                node_dst.vnode_set.len()
-            );
-        }
+        return Ok(());
     }
 
-    pub fn show_vnodes(&self) {
-        for vnode_id in (0..self.vnodes.len()) {
-            if self.vnodes[vnode_id].slots.len() > 0 {
-                println!("vnode id {}: {:?}", vnode_id, self.vnodes[vnode_id].slots);
-            }
-        }
+    cm.add_node(name)?;
+    cm.set_location(name, location)?;
+    cm.set_weight(name, weight)?;
+    println!("add node {} => waiting for allocate", name);
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = app_args();
+    let path = Path::new(matches.get_one::<String>("topology").unwrap());
+    let dry_run = matches.get_flag("dry-run");
+    let json = matches.get_flag("json");
+
+    let (command, sub_matches) = matches.subcommand().unwrap();
+    if command == "compare" {
+        return compare(sub_matches, json);
     }
+    if command == "redis-cluster" {
+        return Ok(redis_cluster(sub_matches)?);
+    }
+    let mut cm = if command == "init" {
+        init(sub_matches, path)?
+    } else {
+        ClusterManager::from_snapshot(&Snapshot::load(path)?)?
+    };
 
-    pub fn show_nodes(&self) {
-        let mut i = 0;
-        let mut total_vnodes = 0;
-        let mut total_slots = 0;
-        for (_, node) in &self.nodes {
-            i += 1;
-            let mut primary_slots = 0;
-            let mut secondary_slots = 0;
-            total_vnodes += node.vnode_set.len();
+    match command {
+        "allocate" => {
+            let names: Vec<&str> = sub_matches
+                .get_many::<String>("names")
+                .unwrap_or_default()
+                .map(String::as_str)
+                .collect();
+            cm.allocate(&names)?;
+            cm.show_nodes();
+            print_overflow(&cm);
+        }
+        "add-node" => add_node(&mut cm, sub_matches, json)?,
+        "remove-node" => {
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            let limits = migration_limits(sub_matches)?;
+            let plan = cm.decommission(name)?;
+            print_plan(
+                &cm,
+                &format!("remove node {}", name),
+                &plan,
+                limits.as_ref(),
+                json,
+            );
+        }
+        "show" => {
             println!(
-                "============= Node {} => name: {}, vnodes count: {}",
-                i,
-                node.name,
-                node.vnode_set.len()
+                "seed: {}, vnodes: {}, slots: {}, replica num: {}, rule: {}, hash: {}",
+                cm.seed(),
+                cm.vnode_num(),
+                cm.slot_num(),
+                cm.replica_num(),
+                cm.placement_rule(),
+                cm.hash_algorithm()
             );
-            for vnode_id in &node.vnode_set {
-                let vnode_index = *vnode_id as usize;
-                if self.vnodes[vnode_index].slots.len() > 0 {
-                    for slot in &self.vnodes[vnode_index].slots {
-                        match slot.role {
-                            Role::PRIMARY => primary_slots += 1,
-                            Role::SECONDARY => secondary_slots += 1,
-                        }
+            if sub_matches.get_flag("vnodes") {
+                cm.show_vnodes();
+            }
+            cm.show_nodes();
+            println!("placement violations: {}", cm.validate_placement().len());
+            return Ok(());
+        }
+        "stats" => {
+            if let Some(level) = sub_matches.get_one::<String>("level") {
+                let domains = cm.domain_stats(level.parse()?);
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&domains)?);
+                } else {
+                    println!("{}", DomainStats::header());
+                    for domain in &domains {
+                        print!("{}", domain);
                     }
-                    //println!("{:?}", self.vnodes[vnode_index].slots);
                 }
+                return Ok(());
             }
-            println!(
-                "Slots: primary {}, secondary {}",
-                primary_slots, secondary_slots
-            );
-            total_slots += primary_slots + secondary_slots;
+            let stats = cm.balance_stats();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+            } else {
+                print!("{}", stats);
+            }
+            return Ok(());
         }
-
-        println!(
-            "total_vnodes: {}, total_slots {}",
-            total_vnodes, total_slots
-        );
+        "simulate" => return simulate(&cm, sub_matches, json),
+        "lookup" => {
+            for key in sub_matches.get_many::<String>("keys").unwrap() {
+                for replica in cm.lookup(key.as_bytes())? {
+                    println!(
+                        "{} => slot {}, vnode {}, {} on {}",
+                        key, replica.slot_id, replica.vnode_id, replica.role, replica.node_name
+                    );
+                }
+            }
+            return Ok(());
+        }
+        _ => {}
     }
-}
-
-fn main() {
-    let mut cm = ClusterManager::new();
 
-    cm.init_vnodes(65536);
-    cm.init_slots(128);
-    cm.show_vnodes();
-
-    cm.allocate(&["a", "b", "c", "d", "e", "f"]);
-    cm.show_nodes();
-
-    cm.scale("ggg");
-    cm.scale("hhh");
-    cm.show_nodes();
+    if dry_run {
+        println!("dry run => {} not written", path.display());
+    } else {
+        cm.snapshot().save(path)?;
+    }
+    Ok(())
 }
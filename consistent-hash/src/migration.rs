@@ -0,0 +1,199 @@
+use crate::cluster::{ClusterManager, Node, Slot};
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// One vnode handed over from one node to another, with the slot replicas
+/// it carries.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationStep {
+    pub vnode_id: u64,
+    pub from: String,
+    pub to: String,
+    pub slots: Vec<Slot>,
+}
+
+/// The moves produced by one topology change, in execution order.
+///
+/// Nodes in `added_nodes` must exist before the first step runs and nodes
+/// in `removed_nodes` own nothing once the last step has run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationPlan {
+    pub added_nodes: Vec<String>,
+    pub removed_nodes: Vec<String>,
+    pub steps: Vec<MigrationStep>,
+}
+
+impl MigrationPlan {
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Returns the number of slot replicas carried by all steps.
+    pub fn slot_moves(&self) -> usize {
+        self.steps.iter().map(|step| step.slots.len()).sum()
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(|e| Error::InvalidPlan(e.to_string()))
+    }
+
+    pub fn from_json(s: &str) -> Result<MigrationPlan> {
+        serde_json::from_str(s).map_err(|e| Error::InvalidPlan(e.to_string()))
+    }
+}
+
+impl fmt::Display for MigrationPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for name in &self.added_nodes {
+            writeln!(f, "add node {}", name)?;
+        }
+        for step in &self.steps {
+            write!(f, "vnode {}: {} -> {}", step.vnode_id, step.from, step.to)?;
+            for slot in &step.slots {
+                write!(f, ", slot {} {:?}", slot.id, slot.role)?;
+            }
+            writeln!(f)?;
+        }
+        for name in &self.removed_nodes {
+            writeln!(f, "remove node {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+impl ClusterManager {
+    pub(crate) fn migration_step(&self, vnode_id: u64, from: &str, to: &str) -> MigrationStep {
+        MigrationStep {
+            vnode_id,
+            from: String::from(from),
+            to: String::from(to),
+            slots: self.vnodes[vnode_id as usize].slots.clone(),
+        }
+    }
+
+    /// Moves one vnode as described by `step`.
+    ///
+    /// Fails without changing anything if the vnode is no longer owned by
+    /// `step.from` or the destination node does not exist.
+    pub fn apply_step(&mut self, step: &MigrationStep) -> Result<()> {
+        let owner = self.vnode_owner(step.vnode_id)?;
+        if owner != Some(step.from.as_str()) {
+            return Err(Error::StaleStep {
+                vnode_id: step.vnode_id,
+                expected: step.from.clone(),
+                actual: owner.map(String::from),
+            });
+        }
+        if !self.nodes.contains_key(&step.to) {
+            return Err(Error::NodeNotFound(step.to.clone()));
+        }
+        if let Some(node_src) = self.nodes.get_mut(&step.from) {
+            node_src.drop_vnode(step.vnode_id);
+        }
+        if let Some(node_dst) = self.nodes.get_mut(&step.to) {
+            node_dst.pickup_vnode(step.vnode_id);
+        }
+        self.vnodes[step.vnode_id as usize].assign(Some(step.to.clone()));
+        Ok(())
+    }
+
+    /// Runs a whole plan: adds its nodes, applies every step in order and
+    /// removes the nodes it empties.
+    ///
+    /// A plan computed on a clone, e.g. `cm.clone().scale("x")`, can be
+    /// reviewed first and then replayed here. Steps already applied stay
+    /// applied if a later one fails.
+    pub fn apply_plan(&mut self, plan: &MigrationPlan) -> Result<()> {
+        for name in &plan.added_nodes {
+            if !self.nodes.contains_key(name) {
+                self.nodes.insert(name.clone(), Node::new(name));
+            }
+        }
+        for step in &plan.steps {
+            self.apply_step(step)?;
+        }
+        for name in &plan.removed_nodes {
+            if let Some(node) = self.nodes.get(name) {
+                if !node.vnode_set.is_empty() {
+                    return Err(Error::NodeNotEmpty(name.clone()));
+                }
+                self.nodes.remove(name);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_cluster() -> ClusterManager {
+        let mut cm = ClusterManager::with_seed(5);
+        cm.init_vnodes(1024).unwrap();
+        cm.init_slots(32).unwrap();
+        cm.allocate(&["a", "b", "c", "d"]).unwrap();
+        cm
+    }
+
+    fn layout(cm: &ClusterManager) -> Vec<Option<String>> {
+        cm.vnodes()
+            .iter()
+            .map(|v| v.node_name().map(String::from))
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_replay() {
+        let mut cm = new_cluster();
+        let mut planned = cm.clone();
+        let plan = planned.scale("e").unwrap();
+        assert_eq!(plan.added_nodes, vec![String::from("e")]);
+        assert!(plan.steps.iter().all(|step| step.to == "e"));
+        for step in &plan.steps {
+            assert_eq!(step.slots, cm.vnode(step.vnode_id).unwrap().slots());
+        }
+
+        let plan = MigrationPlan::from_json(&plan.to_json().unwrap()).unwrap();
+        cm.apply_plan(&plan).unwrap();
+        assert_eq!(layout(&cm), layout(&planned));
+        assert_eq!(
+            cm.node("e").unwrap().vnode_set(),
+            planned.node("e").unwrap().vnode_set()
+        );
+
+        let plan = planned.decommission("a").unwrap();
+        assert_eq!(plan.removed_nodes, vec![String::from("a")]);
+        cm.apply_plan(&plan).unwrap();
+        assert!(cm.node("a").is_none());
+        assert_eq!(layout(&cm), layout(&planned));
+    }
+
+    #[test]
+    fn test_apply_step() {
+        let mut cm = new_cluster();
+        let vnode_id = *cm.node("a").unwrap().vnode_set().iter().min().unwrap();
+        let step = cm.migration_step(vnode_id, "a", "b");
+        cm.apply_step(&step).unwrap();
+        assert_eq!(cm.vnode_owner(vnode_id).unwrap(), Some("b"));
+        assert_eq!(
+            cm.apply_step(&step),
+            Err(Error::StaleStep {
+                vnode_id,
+                expected: String::from("a"),
+                actual: Some(String::from("b")),
+            })
+        );
+        let step = cm.migration_step(vnode_id, "b", "x");
+        assert_eq!(
+            cm.apply_step(&step),
+            Err(Error::NodeNotFound(String::from("x")))
+        );
+        assert!(MigrationPlan::from_json("{").is_err());
+    }
+}
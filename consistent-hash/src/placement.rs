@@ -0,0 +1,455 @@
+use crate::cluster::ClusterManager;
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// A level of the failure-domain hierarchy, from a single node up to a
+/// whole region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum DomainLevel {
+    Node,
+    Host,
+    Rack,
+    Zone,
+    Region,
+}
+
+/// Failure domain that two replicas of the same slot must not share.
+pub type AntiAffinity = DomainLevel;
+
+impl DomainLevel {
+    pub const ALL: [DomainLevel; 5] = [
+        DomainLevel::Node,
+        DomainLevel::Host,
+        DomainLevel::Rack,
+        DomainLevel::Zone,
+        DomainLevel::Region,
+    ];
+}
+
+impl fmt::Display for DomainLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DomainLevel::Node => "node",
+            DomainLevel::Host => "host",
+            DomainLevel::Rack => "rack",
+            DomainLevel::Zone => "zone",
+            DomainLevel::Region => "region",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for DomainLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        DomainLevel::ALL
+            .iter()
+            .find(|level| level.to_string() == s)
+            .cloned()
+            .ok_or_else(|| format!("unknown domain level: {}", s))
+    }
+}
+
+/// Where a node sits in the region → zone → rack → host hierarchy.
+///
+/// Domain names are taken as globally unique, as CRUSH bucket names are;
+/// a node missing a level forms a domain of its own at that level.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Location {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rack: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+}
+
+impl Location {
+    /// Returns the domain at `level` of the node `name` placed here.
+    pub fn domain<'a>(&'a self, name: &'a str, level: DomainLevel) -> &'a str {
+        let domain = match level {
+            DomainLevel::Node => None,
+            DomainLevel::Host => self.host.as_deref(),
+            DomainLevel::Rack => self.rack.as_deref(),
+            DomainLevel::Zone => self.zone.as_deref(),
+            DomainLevel::Region => self.region.as_deref(),
+        };
+        domain.unwrap_or(name)
+    }
+}
+
+/// At most `max_replicas` replicas of a slot may share a domain at `level`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DomainConstraint {
+    pub level: DomainLevel,
+    pub max_replicas: usize,
+}
+
+/// Constraints every slot's replica set must meet, in the spirit of CRUSH
+/// rules: `zone=1` spreads replicas over zones, `region=2,host=1` keeps at
+/// most two per region and never two on one host.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlacementRule {
+    pub constraints: Vec<DomainConstraint>,
+}
+
+impl PlacementRule {
+    /// Every replica of a slot in a different domain at `level`.
+    pub fn spread(level: DomainLevel) -> PlacementRule {
+        PlacementRule {
+            constraints: vec![DomainConstraint {
+                level,
+                max_replicas: 1,
+            }],
+        }
+    }
+
+    /// Checks whether the node `name` at `location` may take one more
+    /// replica of a slot whose other replicas sit on `others`.
+    pub fn admits(&self, name: &str, location: &Location, others: &[(&str, &Location)]) -> bool {
+        self.constraints.iter().all(|c| {
+            let domain = location.domain(name, c.level);
+            let count = others
+                .iter()
+                .filter(|(other, loc)| loc.domain(other, c.level) == domain)
+                .count();
+            count < c.max_replicas
+        })
+    }
+}
+
+impl Default for PlacementRule {
+    fn default() -> Self {
+        PlacementRule::spread(DomainLevel::Node)
+    }
+}
+
+impl fmt::Display for PlacementRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, c) in self.constraints.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}={}", c.level, c.max_replicas)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PlacementRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut constraints = Vec::new();
+        for part in s.split(',') {
+            let (level, max_replicas) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected level=count, got {}", part))?;
+            let max_replicas: usize = max_replicas
+                .parse()
+                .map_err(|_| format!("invalid replica count in {}", part))?;
+            if max_replicas == 0 {
+                return Err(format!("replica count must be positive in {}", part));
+            }
+            constraints.push(DomainConstraint {
+                level: level.parse()?,
+                max_replicas,
+            });
+        }
+        Ok(PlacementRule { constraints })
+    }
+}
+
+/// Replicas of one slot that overfill a failure domain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlacementViolation {
+    pub slot_id: u64,
+    pub level: DomainLevel,
+    pub domain: String,
+    pub vnode_ids: Vec<u64>,
+}
+
+impl ClusterManager {
+    pub fn placement_rule(&self) -> &PlacementRule {
+        &self.rule
+    }
+
+    /// Replaces the rule that allocation, scaling and rebalancing follow.
+    pub fn set_placement_rule(&mut self, rule: PlacementRule) {
+        self.rule = rule;
+    }
+
+    /// Shorthand for a rule keeping every replica in its own domain.
+    pub fn set_anti_affinity(&mut self, anti_affinity: AntiAffinity) {
+        self.rule = PlacementRule::spread(anti_affinity);
+    }
+
+    /// Places the node `name` in `rack`; nodes without a rack form their own.
+    pub fn set_rack(&mut self, name: &str, rack: &str) -> Result<()> {
+        let node = self
+            .nodes
+            .get_mut(name)
+            .ok_or_else(|| Error::NodeNotFound(String::from(name)))?;
+        node.location.rack = Some(String::from(rack));
+        Ok(())
+    }
+
+    /// Places the node `name` in the failure-domain hierarchy.
+    pub fn set_location(&mut self, name: &str, location: Location) -> Result<()> {
+        let node = self
+            .nodes
+            .get_mut(name)
+            .ok_or_else(|| Error::NodeNotFound(String::from(name)))?;
+        node.location = location;
+        Ok(())
+    }
+
+    pub(crate) fn domain_of<'a>(&'a self, name: &'a str, level: DomainLevel) -> &'a str {
+        match self.nodes.get(name) {
+            Some(node) => node.location.domain(name, level),
+            None => name,
+        }
+    }
+
+    pub(crate) fn domain_num(&self, level: DomainLevel) -> usize {
+        let domains: HashSet<&str> = self
+            .nodes
+            .keys()
+            .map(|n| self.domain_of(n, level))
+            .collect();
+        domains.len()
+    }
+
+    /// Checks that the cluster has enough domains for every constraint to
+    /// hold `replica_num` replicas.
+    pub(crate) fn check_domains(&self) -> Result<()> {
+        for c in &self.rule.constraints {
+            let available = self.domain_num(c.level);
+            let required = (self.replica_num as usize).div_ceil(c.max_replicas);
+            if available < required {
+                return Err(Error::NotEnoughDomains {
+                    required,
+                    available,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks whether the node `name` may own `vnode_id` without breaking
+    /// the placement rule for any slot on it.
+    pub(crate) fn fits(&self, vnode_id: u64, name: &str) -> bool {
+        let location = match self.nodes.get(name) {
+            Some(node) => &node.location,
+            None => return false,
+        };
+        self.vnodes[vnode_id as usize].slots.iter().all(|slot| {
+            let others: Vec<(&str, &Location)> = self.slot_vnodes[slot.id as usize]
+                .iter()
+                .filter(|other| **other != vnode_id)
+                .filter_map(|other| self.vnodes[*other as usize].node_name.as_deref())
+                .map(|other| (other, &self.nodes[other].location))
+                .collect();
+            self.rule.admits(name, location, &others)
+        })
+    }
+
+    /// Reports every slot whose replicas overfill a failure domain.
+    pub fn validate_placement(&self) -> Vec<PlacementViolation> {
+        let mut violations = Vec::new();
+        for (slot_id, vnode_ids) in self.slot_vnodes.iter().enumerate() {
+            for c in &self.rule.constraints {
+                let mut domains: BTreeMap<&str, Vec<u64>> = BTreeMap::new();
+                for vnode_id in vnode_ids {
+                    if let Some(name) = self.vnodes[*vnode_id as usize].node_name.as_deref() {
+                        domains
+                            .entry(self.domain_of(name, c.level))
+                            .or_default()
+                            .push(*vnode_id);
+                    }
+                }
+                for (domain, vnode_ids) in domains {
+                    if vnode_ids.len() > c.max_replicas {
+                        violations.push(PlacementViolation {
+                            slot_id: slot_id as u64,
+                            level: c.level,
+                            domain: String::from(domain),
+                            vnode_ids,
+                        });
+                    }
+                }
+            }
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_cluster(anti_affinity: AntiAffinity) -> ClusterManager {
+        let mut cm = ClusterManager::with_seed(7);
+        cm.set_anti_affinity(anti_affinity);
+        cm.init_vnodes(256).unwrap();
+        cm.init_slots(16).unwrap();
+        for (name, rack) in &[
+            ("a", "r1"),
+            ("b", "r1"),
+            ("c", "r2"),
+            ("d", "r2"),
+            ("e", "r3"),
+        ] {
+            cm.add_node(name).unwrap();
+            cm.set_rack(name, rack).unwrap();
+        }
+        cm.allocate(&[]).unwrap();
+        cm
+    }
+
+    fn location(region: &str, zone: &str, host: &str) -> Location {
+        Location {
+            region: Some(String::from(region)),
+            zone: Some(String::from(zone)),
+            rack: None,
+            host: Some(String::from(host)),
+        }
+    }
+
+    #[test]
+    fn test_node_anti_affinity() {
+        let mut cm = new_cluster(AntiAffinity::Node);
+        assert!(cm.validate_placement().is_empty());
+        cm.scale("f").unwrap();
+        cm.scale("g").unwrap();
+        assert!(cm.validate_placement().is_empty());
+        cm.decommission("a").unwrap();
+        assert!(cm.validate_placement().is_empty());
+    }
+
+    #[test]
+    fn test_rack_anti_affinity() {
+        let mut cm = new_cluster(AntiAffinity::Rack);
+        assert!(cm.validate_placement().is_empty());
+        cm.add_node("f").unwrap();
+        cm.set_rack("f", "r3").unwrap();
+        cm.decommission("e").unwrap();
+        assert!(cm.validate_placement().is_empty());
+
+        // distinct racks imply distinct nodes
+        cm.set_anti_affinity(AntiAffinity::Node);
+        assert!(cm.validate_placement().is_empty());
+    }
+
+    #[test]
+    fn test_scale_at_location() {
+        let mut cm = new_cluster(AntiAffinity::Rack);
+        let rack = Location {
+            rack: Some(String::from("r3")),
+            ..Location::default()
+        };
+        let plan = cm.scale_weighted_at("f", 2, rack.clone()).unwrap();
+        assert!(plan.steps.iter().all(|step| step.to == "f"));
+        assert!(plan.len() <= 256 * 2 / 7);
+        assert_eq!(cm.node("f").unwrap().location(), &rack);
+        assert!(cm.validate_placement().is_empty());
+    }
+
+    #[test]
+    fn test_decommission_unsatisfiable() {
+        // a is the only node of r1, and every slot needs one replica per rack
+        let mut cm = ClusterManager::with_seed(7);
+        cm.set_anti_affinity(AntiAffinity::Rack);
+        cm.init_vnodes(64).unwrap();
+        cm.init_slots(8).unwrap();
+        for (name, rack) in &[("a", "r1"), ("b", "r2"), ("c", "r3"), ("d", "r3")] {
+            cm.add_node(name).unwrap();
+            cm.set_rack(name, rack).unwrap();
+        }
+        cm.allocate(&[]).unwrap();
+        let vnode_set = cm.node("a").unwrap().vnode_set().clone();
+        match cm.decommission("a") {
+            Err(Error::AntiAffinityUnsatisfiable(vnode_id)) => {
+                assert!(vnode_set.contains(&vnode_id))
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(cm.node("a").unwrap().vnode_set(), &vnode_set);
+        for vnode_id in &vnode_set {
+            assert_eq!(cm.vnode_owner(*vnode_id).unwrap(), Some("a"));
+        }
+    }
+
+    #[test]
+    fn test_validate_placement() {
+        let mut cm = new_cluster(AntiAffinity::Node);
+        let vnode_ids = cm.slot_vnodes(0).unwrap().to_vec();
+        let owner = String::from(cm.vnode_owner(vnode_ids[0]).unwrap().unwrap());
+        cm.vnodes[vnode_ids[1] as usize].node_name = Some(owner.clone());
+        let violations = cm.validate_placement();
+        assert!(violations.contains(&PlacementViolation {
+            slot_id: 0,
+            level: DomainLevel::Node,
+            domain: owner,
+            vnode_ids: vnode_ids[..2].to_vec(),
+        }));
+        assert_eq!(
+            cm.set_rack("x", "r1"),
+            Err(Error::NodeNotFound(String::from("x")))
+        );
+    }
+
+    #[test]
+    fn test_placement_rule() {
+        let rule: PlacementRule = "region=2,host=1".parse().unwrap();
+        assert_eq!(rule.to_string(), "region=2,host=1");
+        assert!("zone".parse::<PlacementRule>().is_err());
+        assert!("zone=0".parse::<PlacementRule>().is_err());
+        assert!("shelf=1".parse::<PlacementRule>().is_err());
+
+        let (eu1, eu2) = (location("eu", "eu-1", "h1"), location("eu", "eu-2", "h2"));
+        let us = location("us", "us-1", "h3");
+        assert!(rule.admits("b", &eu2, &[("a", &eu1)]));
+        assert!(!rule.admits("b", &eu2, &[("a", &eu1), ("c", &eu1)]));
+        assert!(!rule.admits("b", &eu1, &[("a", &eu1)]));
+        assert!(rule.admits("b", &us, &[("a", &eu1), ("c", &eu2)]));
+    }
+
+    #[test]
+    fn test_zone_rule() {
+        let mut cm = ClusterManager::with_seed(7);
+        cm.set_placement_rule("zone=1".parse().unwrap());
+        cm.init_vnodes(512).unwrap();
+        cm.init_slots(16).unwrap();
+        for (i, name) in ["a", "b", "c", "d", "e", "f"].iter().enumerate() {
+            cm.add_node(name).unwrap();
+            let zone = format!("z{}", i % 3);
+            cm.set_location(name, location("eu", &zone, name)).unwrap();
+        }
+        cm.allocate(&[]).unwrap();
+        assert!(cm.validate_placement().is_empty());
+
+        cm.add_node("g").unwrap();
+        cm.set_location("g", location("eu", "z0", "g")).unwrap();
+        cm.rebalance().unwrap();
+        cm.decommission("b").unwrap();
+        assert!(cm.validate_placement().is_empty());
+
+        // three replicas cannot spread within one region
+        cm.set_placement_rule("region=1".parse().unwrap());
+        assert_eq!(
+            cm.check_domains(),
+            Err(Error::NotEnoughDomains {
+                required: 3,
+                available: 1
+            })
+        );
+        assert!(!cm.validate_placement().is_empty());
+    }
+}
@@ -0,0 +1,623 @@
+use crate::error::{Error, Result};
+use crate::hash::{Crc16, PlacementHasher};
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Number of hash slots in a Redis Cluster.
+pub const REDIS_SLOTS: u64 = 16384;
+
+/// Fewest masters `redis-cli --cluster create` accepts.
+pub const REDIS_MIN_MASTERS: usize = 3;
+
+/// Returns the part of `key` Redis Cluster hashes: what lies between the
+/// first `{` and the next `}` if that is not empty, the whole key otherwise.
+pub fn hash_tag(key: &[u8]) -> &[u8] {
+    if let Some(open) = key.iter().position(|b| *b == b'{') {
+        if let Some(len) = key[open + 1..].iter().position(|b| *b == b'}') {
+            if len > 0 {
+                return &key[open + 1..open + 1 + len];
+            }
+        }
+    }
+    key
+}
+
+/// Maps `key` to its Redis Cluster slot, as `CLUSTER KEYSLOT` does.
+pub fn redis_key_slot(key: &[u8]) -> u64 {
+    Crc16.hash(hash_tag(key)) % REDIS_SLOTS
+}
+
+/// Collapses sorted slots into inclusive `(first, last)` ranges.
+fn slot_ranges<'a>(slots: impl IntoIterator<Item = &'a u64>) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for slot in slots {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == *slot => *last = *slot,
+            _ => ranges.push((*slot, *slot)),
+        }
+    }
+    ranges
+}
+
+fn format_ranges(ranges: &[(u64, u64)]) -> Vec<String> {
+    ranges
+        .iter()
+        .map(|(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{}-{}", first, last)
+            }
+        })
+        .collect()
+}
+
+/// A master or replica of a Redis Cluster.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedisNode {
+    /// 40 hex characters, derived from the address so plans are repeatable.
+    pub id: String,
+    pub host: String,
+    pub port: u16,
+    /// Id of the master this node replicates, `None` on a master.
+    pub master: Option<String>,
+    /// Slots the node serves, empty on a replica.
+    pub slots: BTreeSet<u64>,
+    pub config_epoch: u64,
+}
+
+impl RedisNode {
+    /// Parses `host:port`, also accepting the `redis://[:password@]host:port/`
+    /// URLs redis-test connects with.
+    pub fn new(addr: &str) -> Result<RedisNode> {
+        let invalid = || Error::InvalidAddress(String::from(addr));
+        let hostport = addr.strip_prefix("redis://").unwrap_or(addr);
+        let hostport = hostport.trim_end_matches('/');
+        let hostport = hostport.rsplit('@').next().unwrap_or(hostport);
+        let (host, port) = hostport.rsplit_once(':').ok_or_else(invalid)?;
+        let port: u16 = port.parse().map_err(|_| invalid())?;
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let hostport = format!("{}:{}", host, port);
+        let id = (0..3)
+            .map(|seed| {
+                format!(
+                    "{:016x}",
+                    xxhash_rust::xxh64::xxh64(hostport.as_bytes(), seed)
+                )
+            })
+            .collect::<String>()[..40]
+            .to_string();
+        Ok(RedisNode {
+            id,
+            host: String::from(host),
+            port,
+            master: None,
+            slots: BTreeSet::new(),
+            config_epoch: 0,
+        })
+    }
+
+    pub fn addr(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    pub fn is_master(&self) -> bool {
+        self.master.is_none()
+    }
+
+    /// Returns the served slots as inclusive `(first, last)` ranges.
+    pub fn slot_ranges(&self) -> Vec<(u64, u64)> {
+        slot_ranges(&self.slots)
+    }
+}
+
+/// Slots to hand from one master to another, named by node id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reshard {
+    pub from: String,
+    pub to: String,
+    /// `host:port` of the two masters, which a removed master no longer has
+    /// in the cluster.
+    pub from_addr: String,
+    pub to_addr: String,
+    /// Slots moved, sorted.
+    pub slots: Vec<u64>,
+}
+
+fn redis_cli(addr: &str) -> String {
+    match addr.rsplit_once(':') {
+        Some((host, port)) => format!("redis-cli -h {} -p {}", host, port),
+        None => format!("redis-cli -h {}", addr),
+    }
+}
+
+impl Reshard {
+    /// The redis-cli commands moving exactly `slots`, one slot at a time as
+    /// `redis-cli --cluster reshard` does it: mark the slot importing on the
+    /// target and migrating on the source, `MIGRATE` its keys in batches,
+    /// then assign it to the target on both.
+    pub fn commands(&self) -> Vec<String> {
+        let (source, target) = (redis_cli(&self.from_addr), redis_cli(&self.to_addr));
+        let (host, port) = self
+            .to_addr
+            .rsplit_once(':')
+            .unwrap_or((self.to_addr.as_str(), "6379"));
+        let mut commands = Vec::new();
+        for slot in &self.slots {
+            commands.push(format!(
+                "{} CLUSTER SETSLOT {} IMPORTING {}",
+                target, slot, self.from
+            ));
+            commands.push(format!(
+                "{} CLUSTER SETSLOT {} MIGRATING {}",
+                source, slot, self.to
+            ));
+            commands.push(format!(
+                "while keys=$({} CLUSTER GETKEYSINSLOT {} 100) && [ -n \"$keys\" ]; \
+                 do {} MIGRATE {} {} \"\" 0 60000 KEYS $keys; done",
+                source, slot, source, host, port
+            ));
+            for node in &[&target, &source] {
+                commands.push(format!(
+                    "{} CLUSTER SETSLOT {} NODE {}",
+                    node, slot, self.to
+                ));
+            }
+        }
+        commands
+    }
+}
+
+impl fmt::Display for Reshard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "reshard {} slots {} -> {}: {}",
+            self.slots.len(),
+            self.from,
+            self.to,
+            format_ranges(&slot_ranges(&self.slots)).join(" ")
+        )
+    }
+}
+
+/// A `CLUSTER SLOTS` reply, printed the way redis-cli prints replies.
+enum Reply {
+    Integer(u64),
+    Bulk(String),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn format(&self, prefix: &str, out: &mut String) {
+        match self {
+            Reply::Integer(n) => out.push_str(&format!("(integer) {}\n", n)),
+            Reply::Bulk(s) => out.push_str(&format!("\"{}\"\n", s)),
+            Reply::Array(items) if items.is_empty() => out.push_str("(empty array)\n"),
+            Reply::Array(items) => {
+                let width = items.len().to_string().len();
+                let nested = format!("{}{}", prefix, " ".repeat(width + 2));
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(prefix);
+                    }
+                    out.push_str(&format!("{:>width$}) ", i + 1, width = width));
+                    item.format(&nested, out);
+                }
+            }
+        }
+    }
+}
+
+/// A Redis Cluster with 16384 slots, laid out the way
+/// `redis-cli --cluster create` lays it out: every master serves one
+/// contiguous range and replicas are spread over the masters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RedisCluster {
+    nodes: Vec<RedisNode>,
+    current_epoch: u64,
+}
+
+impl RedisCluster {
+    /// Makes the first `nodes.len() / (replicas + 1)` nodes masters and the
+    /// rest replicas, like `redis-cli --cluster create --cluster-replicas`.
+    pub fn create(nodes: &[&str], replicas: usize) -> Result<RedisCluster> {
+        let masters = nodes.len() / (replicas + 1);
+        if masters < REDIS_MIN_MASTERS {
+            return Err(Error::NotEnoughNodes {
+                required: REDIS_MIN_MASTERS * (replicas + 1),
+                available: nodes.len(),
+            });
+        }
+
+        let mut cluster = RedisCluster::default();
+        for addr in nodes {
+            let node = RedisNode::new(addr)?;
+            if cluster.nodes.iter().any(|other| other.id == node.id) {
+                return Err(Error::DuplicateNode(node.addr()));
+            }
+            cluster.nodes.push(node);
+        }
+
+        // the same split redis-cli computes, rounding each boundary
+        let per_master = REDIS_SLOTS as f64 / masters as f64;
+        let mut first = 0;
+        let mut cursor = 0.0;
+        for (i, node) in cluster.nodes[..masters].iter_mut().enumerate() {
+            let mut last = (cursor + per_master - 1.0).round() as u64;
+            if last >= REDIS_SLOTS || i == masters - 1 {
+                last = REDIS_SLOTS - 1;
+            }
+            node.slots = (first..=last).collect();
+            node.config_epoch = i as u64 + 1;
+            first = last + 1;
+            cursor += per_master;
+        }
+        cluster.current_epoch = masters as u64;
+
+        for i in masters..cluster.nodes.len() {
+            let master = cluster.pick_master(&cluster.nodes[i].host, None);
+            cluster.nodes[i].master = Some(master);
+        }
+        Ok(cluster)
+    }
+
+    /// Returns every node, masters first, in the order they joined.
+    pub fn nodes(&self) -> &[RedisNode] {
+        &self.nodes
+    }
+
+    pub fn node(&self, addr: &str) -> Option<&RedisNode> {
+        let addr = RedisNode::new(addr).ok()?.addr();
+        self.nodes.iter().find(|node| node.addr() == addr)
+    }
+
+    pub fn masters(&self) -> Vec<&RedisNode> {
+        self.nodes.iter().filter(|node| node.is_master()).collect()
+    }
+
+    fn replicas_of(&self, id: &str) -> Vec<&RedisNode> {
+        self.nodes
+            .iter()
+            .filter(|node| node.master.as_deref() == Some(id))
+            .collect()
+    }
+
+    /// Returns the master serving `slot_id`.
+    pub fn slot_master(&self, slot_id: u64) -> Option<&RedisNode> {
+        self.nodes.iter().find(|node| node.slots.contains(&slot_id))
+    }
+
+    /// Returns the master serving `key`.
+    pub fn key_master(&self, key: &[u8]) -> Option<&RedisNode> {
+        self.slot_master(redis_key_slot(key))
+    }
+
+    /// Picks the master with the fewest replicas, preferring one on another
+    /// host than `host`, for a replica to follow.
+    fn pick_master(&self, host: &str, except: Option<&str>) -> String {
+        self.masters()
+            .into_iter()
+            .filter(|master| Some(master.id.as_str()) != except)
+            .enumerate()
+            .min_by_key(|(i, master)| (self.replicas_of(&master.id).len(), master.host == host, *i))
+            .map(|(_, master)| master.id.clone())
+            .unwrap_or_default()
+    }
+
+    /// Joins `addr` as an empty master and reshards slots to it until every
+    /// master serves an even share.
+    pub fn add_master(&mut self, addr: &str) -> Result<Vec<Reshard>> {
+        let mut node = RedisNode::new(addr)?;
+        if self.nodes.iter().any(|other| other.id == node.id) {
+            return Err(Error::DuplicateNode(node.addr()));
+        }
+        self.current_epoch += 1;
+        node.config_epoch = self.current_epoch;
+        let masters = self.masters().len();
+        self.nodes.insert(masters, node);
+        Ok(self.rebalance(None))
+    }
+
+    /// Reshards every slot of the master `addr` to the others, hands its
+    /// replicas to the masters with the fewest, then forgets it. Refuses to
+    /// leave fewer than `REDIS_MIN_MASTERS` masters.
+    pub fn remove_master(&mut self, addr: &str) -> Result<Vec<Reshard>> {
+        let id = match self.node(addr) {
+            Some(node) if node.is_master() => node.id.clone(),
+            _ => return Err(Error::NodeNotFound(String::from(addr))),
+        };
+        let masters = self.masters().len();
+        if masters <= REDIS_MIN_MASTERS {
+            return Err(Error::NotEnoughNodes {
+                required: REDIS_MIN_MASTERS,
+                available: masters - 1,
+            });
+        }
+
+        let reshards = self.rebalance(Some(&id));
+        for i in 0..self.nodes.len() {
+            if self.nodes[i].master.as_deref() == Some(id.as_str()) {
+                let master = self.pick_master(&self.nodes[i].host, Some(&id));
+                self.nodes[i].master = Some(master);
+            }
+        }
+        self.nodes.retain(|node| node.id != id);
+        Ok(reshards)
+    }
+
+    /// Moves slots from masters above their share to masters below it,
+    /// lowest slots first; `Reshard::commands` moves exactly those.
+    ///
+    /// `leaving` gets no share. The rounding goes to the masters already
+    /// serving the most, so nothing moves that need not.
+    fn rebalance(&mut self, leaving: Option<&str>) -> Vec<Reshard> {
+        let mut order: Vec<usize> = (0..self.nodes.len())
+            .filter(|i| self.nodes[*i].is_master() && Some(self.nodes[*i].id.as_str()) != leaving)
+            .collect();
+        order.sort_by_key(|i| (std::cmp::Reverse(self.nodes[*i].slots.len()), *i));
+        let base = REDIS_SLOTS as usize / order.len();
+        let extra = REDIS_SLOTS as usize % order.len();
+        let mut target = vec![0; self.nodes.len()];
+        for (rank, i) in order.iter().enumerate() {
+            target[*i] = base + usize::from(rank < extra);
+        }
+
+        let mut reshards = Vec::new();
+        loop {
+            let held = |i: &usize| self.nodes[*i].slots.len();
+            let donor = (0..self.nodes.len())
+                .filter(|i| held(i) > target[*i])
+                .max_by_key(|i| (held(i) - target[*i], std::cmp::Reverse(*i)));
+            let taker = (0..self.nodes.len())
+                .filter(|i| held(i) < target[*i])
+                .max_by_key(|i| (target[*i] - held(i), std::cmp::Reverse(*i)));
+            let (from, to) = match (donor, taker) {
+                (Some(from), Some(to)) => (from, to),
+                _ => break,
+            };
+            let count = (held(&from) - target[from]).min(target[to] - held(&to));
+            let slots: Vec<u64> = self.nodes[from].slots.iter().take(count).copied().collect();
+            for slot in &slots {
+                self.nodes[from].slots.remove(slot);
+                self.nodes[to].slots.insert(*slot);
+            }
+            self.current_epoch += 1;
+            self.nodes[to].config_epoch = self.current_epoch;
+            reshards.push(Reshard {
+                from: self.nodes[from].id.clone(),
+                to: self.nodes[to].id.clone(),
+                from_addr: self.nodes[from].addr(),
+                to_addr: self.nodes[to].addr(),
+                slots,
+            });
+        }
+        reshards
+    }
+
+    /// Prints what `CLUSTER SLOTS` replies, as redis-cli shows it.
+    pub fn cluster_slots(&self) -> String {
+        let endpoint = |node: &RedisNode| {
+            Reply::Array(vec![
+                Reply::Bulk(node.host.clone()),
+                Reply::Integer(node.port as u64),
+                Reply::Bulk(node.id.clone()),
+            ])
+        };
+        let mut ranges: Vec<(u64, u64, &RedisNode)> = self
+            .masters()
+            .into_iter()
+            .flat_map(|master| {
+                master
+                    .slot_ranges()
+                    .into_iter()
+                    .map(move |(first, last)| (first, last, master))
+            })
+            .collect();
+        ranges.sort_by_key(|(first, _, _)| *first);
+
+        let reply = Reply::Array(
+            ranges
+                .into_iter()
+                .map(|(first, last, master)| {
+                    let mut items = vec![
+                        Reply::Integer(first),
+                        Reply::Integer(last),
+                        endpoint(master),
+                    ];
+                    items.extend(self.replicas_of(&master.id).into_iter().map(endpoint));
+                    Reply::Array(items)
+                })
+                .collect(),
+        );
+        let mut out = String::new();
+        reply.format("", &mut out);
+        out
+    }
+
+    /// Prints what `CLUSTER NODES` replies, one line per node.
+    pub fn cluster_nodes(&self) -> String {
+        let mut out = String::new();
+        for node in &self.nodes {
+            let (flags, master, epoch) = match &node.master {
+                Some(id) => {
+                    let epoch = self
+                        .nodes
+                        .iter()
+                        .find(|master| &master.id == id)
+                        .map_or(0, |master| master.config_epoch);
+                    ("slave", id.as_str(), epoch)
+                }
+                None => ("master", "-", node.config_epoch),
+            };
+            let mut line = format!(
+                "{} {}@{} {} {} 0 0 {} connected",
+                node.id,
+                node.addr(),
+                node.port as u32 + 10000,
+                flags,
+                master,
+                epoch
+            );
+            for range in format_ranges(&node.slot_ranges()) {
+                line.push(' ');
+                line.push_str(&range);
+            }
+            out.push_str(&line);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_cluster() -> RedisCluster {
+        let nodes: Vec<String> = (30001..=30006)
+            .map(|port| format!("127.0.0.1:{}", port))
+            .collect();
+        let nodes: Vec<&str> = nodes.iter().map(String::as_str).collect();
+        RedisCluster::create(&nodes, 1).unwrap()
+    }
+
+    #[test]
+    fn test_key_slot() {
+        assert_eq!(hash_tag(b"{user1000}.following"), b"user1000");
+        assert_eq!(hash_tag(b"foo{}{bar}"), b"foo{}{bar}");
+        assert_eq!(hash_tag(b"foo{{bar}}zap"), b"{bar");
+        assert_eq!(hash_tag(b"foo{bar}{zap}"), b"bar");
+        assert_eq!(hash_tag(b"foo{bar"), b"foo{bar");
+        assert_eq!(redis_key_slot(b"123456789"), 12739);
+        assert_eq!(redis_key_slot(b"foo"), 12182);
+        assert_eq!(redis_key_slot(b"bar"), 5061);
+        assert_eq!(
+            redis_key_slot(b"{user1000}.following"),
+            redis_key_slot(b"{user1000}.followers")
+        );
+    }
+
+    #[test]
+    fn test_create() {
+        let cluster = new_cluster();
+        let ranges: Vec<Vec<(u64, u64)>> = cluster
+            .masters()
+            .iter()
+            .map(|master| master.slot_ranges())
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![vec![(0, 5460)], vec![(5461, 10922)], vec![(10923, 16383)]]
+        );
+        for (i, replica) in cluster.nodes()[3..].iter().enumerate() {
+            assert_eq!(replica.master.as_ref(), Some(&cluster.nodes()[i].id));
+        }
+        assert_eq!(cluster.key_master(b"foo").unwrap().port, 30003);
+        assert_eq!(
+            RedisCluster::create(&["127.0.0.1:30001", "127.0.0.1:30002"], 0),
+            Err(Error::NotEnoughNodes {
+                required: 3,
+                available: 2
+            })
+        );
+        assert_eq!(
+            RedisNode::new("redis://:secret@127.0.0.1:30001/")
+                .unwrap()
+                .addr(),
+            "127.0.0.1:30001"
+        );
+        assert!(RedisNode::new("127.0.0.1").is_err());
+    }
+
+    #[test]
+    fn test_output() {
+        let cluster = new_cluster();
+        let nodes = cluster.cluster_nodes();
+        let first: Vec<&str> = nodes.lines().next().unwrap().split(' ').collect();
+        assert_eq!(first[0].len(), 40);
+        assert_eq!(
+            &first[1..],
+            &[
+                "127.0.0.1:30001@40001",
+                "master",
+                "-",
+                "0",
+                "0",
+                "1",
+                "connected",
+                "0-5460"
+            ]
+        );
+        let last: Vec<&str> = nodes.lines().last().unwrap().split(' ').collect();
+        assert_eq!(last[2..4], ["slave", cluster.nodes()[2].id.as_str()]);
+        assert_eq!(last[6], "3");
+
+        let slots = cluster.cluster_slots();
+        let lines: Vec<&str> = slots.lines().collect();
+        assert_eq!(lines.len(), 3 * 8);
+        assert_eq!(lines[0], "1) 1) (integer) 0");
+        assert_eq!(lines[1], "   2) (integer) 5460");
+        assert_eq!(lines[2], "   3) 1) \"127.0.0.1\"");
+        assert_eq!(lines[3], "      2) (integer) 30001");
+        assert_eq!(lines[6], "      2) (integer) 30004");
+        assert_eq!(lines[8], "2) 1) (integer) 5461");
+    }
+
+    #[test]
+    fn test_reshard() {
+        let mut cluster = new_cluster();
+        let reshards = cluster.add_master("127.0.0.1:30007").unwrap();
+        let new_id = cluster.node("127.0.0.1:30007").unwrap().id.clone();
+        assert!(reshards.iter().all(|r| r.to == new_id));
+        assert_eq!(reshards.iter().map(|r| r.slots.len()).sum::<usize>(), 4096);
+        for master in cluster.masters() {
+            assert_eq!(master.slots.len(), 4096);
+        }
+        assert_eq!(
+            cluster.add_master("127.0.0.1:30001"),
+            Err(Error::DuplicateNode(String::from("127.0.0.1:30001")))
+        );
+
+        let reshard = &reshards[0];
+        let slot = reshard.slots[0];
+        let commands = reshard.commands();
+        assert_eq!(commands.len(), 5 * reshard.slots.len());
+        assert_eq!(
+            commands[0],
+            format!(
+                "redis-cli -h 127.0.0.1 -p 30007 CLUSTER SETSLOT {} IMPORTING {}",
+                slot, reshard.from
+            )
+        );
+        assert!(commands[1].starts_with(&format!("redis-cli -h {} -p ", "127.0.0.1")));
+        assert!(commands[1].ends_with(&format!("SETSLOT {} MIGRATING {}", slot, new_id)));
+        assert!(commands[2].contains("MIGRATE 127.0.0.1 30007 \"\" 0 60000 KEYS $keys"));
+        assert!(commands[3].ends_with(&format!("SETSLOT {} NODE {}", slot, new_id)));
+
+        let reshards = cluster.remove_master("127.0.0.1:30001").unwrap();
+        assert_eq!(reshards.iter().map(|r| r.slots.len()).sum::<usize>(), 4096);
+        assert!(cluster.node("127.0.0.1:30001").is_none());
+        let masters = cluster.masters();
+        assert_eq!(masters.len(), 3);
+        let served: usize = masters.iter().map(|master| master.slots.len()).sum();
+        assert_eq!(served, REDIS_SLOTS as usize);
+        assert!(masters.iter().all(|master| master.slots.len() >= 5461));
+        assert!(cluster.replicas_of(&new_id).len() == 1);
+        assert_eq!(
+            cluster.remove_master("127.0.0.1:30004"),
+            Err(Error::NodeNotFound(String::from("127.0.0.1:30004")))
+        );
+        let addr = cluster.masters()[0].addr();
+        assert_eq!(
+            cluster.remove_master(&addr),
+            Err(Error::NotEnoughNodes {
+                required: REDIS_MIN_MASTERS,
+                available: REDIS_MIN_MASTERS - 1,
+            })
+        );
+        assert_eq!(cluster.masters().len(), REDIS_MIN_MASTERS);
+    }
+}
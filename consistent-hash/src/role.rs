@@ -0,0 +1,159 @@
+use crate::cluster::ClusterManager;
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// What a replica of a slot does for it.
+///
+/// Primaries and secondaries hold the data and vote; learners, witnesses
+/// and arbiters stay out of the voting quorum. Only a secondary may take
+/// over as primary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Role {
+    Primary,
+    Secondary,
+    /// Holds a full copy that is still catching up.
+    Learner,
+    /// Keeps the replication log but no data.
+    Witness,
+    /// Keeps neither log nor data, only breaks ties.
+    Arbiter,
+}
+
+impl Role {
+    pub const ALL: [Role; 5] = [
+        Role::Primary,
+        Role::Secondary,
+        Role::Learner,
+        Role::Witness,
+        Role::Arbiter,
+    ];
+
+    pub fn is_voter(self) -> bool {
+        matches!(self, Role::Primary | Role::Secondary)
+    }
+
+    pub fn holds_data(self) -> bool {
+        matches!(self, Role::Primary | Role::Secondary | Role::Learner)
+    }
+
+    pub fn can_promote(self) -> bool {
+        self == Role::Secondary
+    }
+
+    /// One primary followed by `replica_num - 1` secondaries.
+    pub fn layout(replica_num: u64) -> Vec<Role> {
+        (0..replica_num)
+            .map(|r| {
+                if r == 0 {
+                    Role::Primary
+                } else {
+                    Role::Secondary
+                }
+            })
+            .collect()
+    }
+
+    /// Checks that `roles` starts with the only primary.
+    pub fn check_layout(roles: &[Role]) -> std::result::Result<(), String> {
+        if roles.first() != Some(&Role::Primary) {
+            return Err(String::from("the first replica must be the primary"));
+        }
+        if roles[1..].contains(&Role::Primary) {
+            return Err(String::from("only the first replica may be the primary"));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Role::Primary => "primary",
+            Role::Secondary => "secondary",
+            Role::Learner => "learner",
+            Role::Witness => "witness",
+            Role::Arbiter => "arbiter",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Role::ALL
+            .iter()
+            .find(|role| role.to_string() == s)
+            .cloned()
+            .ok_or_else(|| format!("unknown role: {}", s))
+    }
+}
+
+impl ClusterManager {
+    /// Returns the role of every replica position, primary first.
+    pub fn roles(&self) -> &[Role] {
+        &self.roles
+    }
+
+    /// Sets the role of every replica position, which also sets the
+    /// replica number.
+    ///
+    /// Slots already placed are placed again, as with `set_replica_num`.
+    pub fn set_roles(&mut self, roles: &[Role]) -> Result<()> {
+        Role::check_layout(roles).map_err(Error::InvalidRoles)?;
+        self.check_replica_num(roles.len() as u64)?;
+        self.roles = roles.to_vec();
+        self.replica_num = roles.len() as u64;
+        if !self.slot_vnodes.is_empty() {
+            self.init_slots(self.max_slot_id)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        for role in &Role::ALL {
+            assert_eq!(role.to_string().parse(), Ok(*role));
+        }
+        assert_eq!(
+            Role::layout(3),
+            vec![Role::Primary, Role::Secondary, Role::Secondary]
+        );
+        assert!(Role::check_layout(&Role::layout(1)).is_ok());
+        assert!(Role::check_layout(&[]).is_err());
+        assert!(Role::check_layout(&[Role::Secondary, Role::Primary]).is_err());
+        assert!(Role::check_layout(&[Role::Primary, Role::Primary]).is_err());
+        assert!(!Role::Witness.is_voter() && !Role::Arbiter.holds_data());
+        assert!(Role::Learner.holds_data() && !Role::Learner.is_voter());
+    }
+
+    #[test]
+    fn test_set_roles() {
+        let mut cm = ClusterManager::with_seed(3);
+        cm.init_vnodes(256).unwrap();
+        cm.init_slots(16).unwrap();
+        let roles = [Role::Primary, Role::Secondary, Role::Learner, Role::Arbiter];
+        cm.set_roles(&roles).unwrap();
+        assert_eq!(cm.replica_num(), 4);
+        cm.allocate(&["a", "b", "c", "d"]).unwrap();
+        for slot_id in 0..16 {
+            let replicas = cm.slot_replicas(slot_id).unwrap();
+            let got: Vec<Role> = replicas.iter().map(|r| r.role).collect();
+            assert_eq!(got, roles);
+        }
+        assert!(matches!(
+            cm.set_roles(&[Role::Learner]),
+            Err(Error::InvalidRoles(_))
+        ));
+        cm.set_replica_num(2).unwrap();
+        assert_eq!(cm.roles(), &Role::layout(2)[..]);
+    }
+}
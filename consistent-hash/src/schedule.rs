@@ -0,0 +1,255 @@
+use crate::error::{Error, Result};
+use crate::migration::MigrationPlan;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Bounds on the copies running at once, and how long one takes.
+///
+/// A bound of zero lifts it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MigrationLimits {
+    /// Copies one node sends at once.
+    pub per_source: usize,
+    /// Copies one node receives at once.
+    pub per_target: usize,
+    /// Copies running at once in the whole cluster.
+    pub cluster: usize,
+    /// Seconds one slot replica takes to copy.
+    pub slot_copy_secs: f64,
+}
+
+impl MigrationLimits {
+    /// Fails unless `slot_copy_secs` is finite and not negative.
+    pub fn new(
+        per_source: usize,
+        per_target: usize,
+        cluster: usize,
+        slot_copy_secs: f64,
+    ) -> Result<MigrationLimits> {
+        if !slot_copy_secs.is_finite() || slot_copy_secs < 0.0 {
+            return Err(Error::InvalidLimits(format!(
+                "slot copy time {}s",
+                slot_copy_secs
+            )));
+        }
+        Ok(MigrationLimits {
+            per_source,
+            per_target,
+            cluster,
+            slot_copy_secs,
+        })
+    }
+}
+
+impl Default for MigrationLimits {
+    fn default() -> MigrationLimits {
+        MigrationLimits {
+            per_source: 2,
+            per_target: 2,
+            cluster: 16,
+            slot_copy_secs: 1.0,
+        }
+    }
+}
+
+/// Slot replicas copied from one node to another as a unit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transfer {
+    /// What moves: a vnode id in a `MigrationPlan`, a slot id elsewhere.
+    /// Transfers sharing an id run in the order given.
+    pub id: u64,
+    pub from: String,
+    pub to: String,
+    pub slots: usize,
+}
+
+/// Transfers that run side by side; the next wave starts once the longest
+/// one is done.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Wave {
+    pub transfers: Vec<Transfer>,
+    pub slots: usize,
+    pub secs: f64,
+}
+
+/// A migration split into waves that keep within `MigrationLimits`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MigrationSchedule {
+    pub waves: Vec<Wave>,
+    pub slots: usize,
+    /// Estimated time until the last wave is done.
+    pub secs: f64,
+}
+
+fn within(count: Option<&usize>, limit: usize) -> bool {
+    limit == 0 || count.copied().unwrap_or(0) < limit
+}
+
+impl MigrationSchedule {
+    /// Packs `transfers` into waves, each taking every pending transfer, in
+    /// order, that still fits the limits of its source, its target and the
+    /// cluster.
+    pub fn new(transfers: Vec<Transfer>, limits: &MigrationLimits) -> MigrationSchedule {
+        let mut pending = transfers;
+        let mut waves = Vec::new();
+        while !pending.is_empty() {
+            let mut sending: HashMap<String, usize> = HashMap::new();
+            let mut receiving: HashMap<String, usize> = HashMap::new();
+            let mut seen: HashSet<u64> = HashSet::new();
+            let mut wave = Vec::new();
+            let mut rest = Vec::new();
+            for transfer in pending {
+                let fits = seen.insert(transfer.id)
+                    && (limits.cluster == 0 || wave.len() < limits.cluster)
+                    && within(sending.get(&transfer.from), limits.per_source)
+                    && within(receiving.get(&transfer.to), limits.per_target);
+                if fits {
+                    *sending.entry(transfer.from.clone()).or_insert(0) += 1;
+                    *receiving.entry(transfer.to.clone()).or_insert(0) += 1;
+                    wave.push(transfer);
+                } else {
+                    rest.push(transfer);
+                }
+            }
+            pending = rest;
+            let longest = wave.iter().map(|t| t.slots).max().unwrap_or(0);
+            waves.push(Wave {
+                slots: wave.iter().map(|t| t.slots).sum(),
+                secs: longest as f64 * limits.slot_copy_secs,
+                transfers: wave,
+            });
+        }
+        MigrationSchedule {
+            slots: waves.iter().map(|wave| wave.slots).sum(),
+            secs: waves.iter().map(|wave| wave.secs).sum(),
+            waves,
+        }
+    }
+}
+
+impl fmt::Display for MigrationSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, wave) in self.waves.iter().enumerate() {
+            writeln!(
+                f,
+                "wave {}: transfers {}, slots {}, {:.1}s",
+                i + 1,
+                wave.transfers.len(),
+                wave.slots,
+                wave.secs
+            )?;
+            for transfer in &wave.transfers {
+                writeln!(
+                    f,
+                    "  {}: {} -> {}, slots {}",
+                    transfer.id, transfer.from, transfer.to, transfer.slots
+                )?;
+            }
+        }
+        writeln!(
+            f,
+            "schedule => waves: {}, slots: {}, estimated duration: {:.1}s",
+            self.waves.len(),
+            self.slots,
+            self.secs
+        )
+    }
+}
+
+impl MigrationPlan {
+    /// Schedules the steps of the plan; vnodes carrying no slot replica
+    /// move without copying anything and are left out.
+    pub fn schedule(&self, limits: &MigrationLimits) -> MigrationSchedule {
+        let transfers = self
+            .steps
+            .iter()
+            .filter(|step| !step.slots.is_empty())
+            .map(|step| Transfer {
+                id: step.vnode_id,
+                from: step.from.clone(),
+                to: step.to.clone(),
+                slots: step.slots.len(),
+            })
+            .collect();
+        MigrationSchedule::new(transfers, limits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::ClusterManager;
+
+    fn transfer(id: u64, from: &str, to: &str, slots: usize) -> Transfer {
+        Transfer {
+            id,
+            from: String::from(from),
+            to: String::from(to),
+            slots,
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let transfers = vec![
+            transfer(0, "a", "d", 2),
+            transfer(1, "a", "d", 1),
+            transfer(2, "b", "d", 3),
+            transfer(3, "c", "e", 1),
+            transfer(0, "d", "e", 2),
+        ];
+        let limits = MigrationLimits::new(1, 2, 0, 0.5).unwrap();
+        let schedule = MigrationSchedule::new(transfers.clone(), &limits);
+        let ids: Vec<Vec<u64>> = schedule
+            .waves
+            .iter()
+            .map(|wave| wave.transfers.iter().map(|t| t.id).collect())
+            .collect();
+        assert_eq!(ids, vec![vec![0, 2, 3], vec![1, 0]]);
+        assert_eq!(schedule.slots, 9);
+        assert_eq!(schedule.secs, 1.5 + 1.0);
+
+        let limits = MigrationLimits {
+            cluster: 1,
+            ..limits
+        };
+        let schedule = MigrationSchedule::new(transfers, &limits);
+        assert_eq!(schedule.waves.len(), 5);
+        assert_eq!(schedule.secs, 4.5);
+        assert!(MigrationSchedule::new(Vec::new(), &limits).waves.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_limits() {
+        for secs in [-1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                MigrationLimits::new(2, 2, 16, secs),
+                Err(Error::InvalidLimits(_))
+            ));
+        }
+        let free = MigrationLimits::new(0, 0, 0, 0.0).unwrap();
+        let schedule = MigrationSchedule::new(vec![transfer(0, "a", "b", 3)], &free);
+        assert_eq!(schedule.secs, 0.0);
+    }
+
+    #[test]
+    fn test_plan_schedule() {
+        let mut cm = ClusterManager::with_seed(3);
+        cm.init_vnodes(256).unwrap();
+        cm.init_slots(64).unwrap();
+        cm.allocate(&["a", "b", "c", "d"]).unwrap();
+        let plan = cm.scale("e").unwrap();
+        let limits = MigrationLimits::default();
+        let schedule = plan.schedule(&limits);
+        assert_eq!(schedule.slots, plan.slot_moves());
+        for wave in &schedule.waves {
+            assert!(wave.transfers.len() <= limits.per_target);
+        }
+        let unlimited = MigrationLimits {
+            per_target: 0,
+            ..limits
+        };
+        assert!(plan.schedule(&unlimited).secs < schedule.secs);
+    }
+}
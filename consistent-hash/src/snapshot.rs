@@ -0,0 +1,432 @@
+use crate::cluster::{ClusterManager, Node, PlacementRng, Slot, VNode};
+use crate::error::{Error, Result};
+use crate::hash::HashAlgorithm;
+use crate::placement::{Location, PlacementRule};
+use crate::role::Role;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// Version written into every snapshot; loading rejects newer ones.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeSnapshot {
+    pub name: String,
+    pub weight: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rack: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// Owned vnode ids, sorted.
+    pub vnodes: Vec<u64>,
+}
+
+/// The full topology of a `ClusterManager` in a stable, diffable form.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub seed: u64,
+    /// Numbers drawn from the seeded generator so far.
+    #[serde(default)]
+    pub rng_draws: u64,
+    pub replica_num: u64,
+    /// Role of every replica position; empty means the default layout.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<Role>,
+    pub tolerance: u64,
+    /// See `ClusterManager::set_load_epsilon`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load_epsilon: Option<f64>,
+    #[serde(default)]
+    pub rule: PlacementRule,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    pub vnode_num: u64,
+    /// Nodes sorted by name.
+    pub nodes: Vec<NodeSnapshot>,
+    /// Vnode ids holding each slot, primary first.
+    pub slots: Vec<Vec<u64>>,
+}
+
+fn invalid(msg: String) -> Error {
+    Error::InvalidSnapshot(msg)
+}
+
+impl Snapshot {
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(|e| invalid(e.to_string()))
+    }
+
+    pub fn from_json(s: &str) -> Result<Snapshot> {
+        serde_json::from_str(s).map_err(|e| invalid(e.to_string()))
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).map_err(|e| invalid(e.to_string()))
+    }
+
+    pub fn from_toml(s: &str) -> Result<Snapshot> {
+        toml::from_str(s).map_err(|e| invalid(e.to_string()))
+    }
+
+    /// Writes the snapshot as TOML if `path` ends in `.toml`, JSON otherwise.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let data = if is_toml(path) {
+            self.to_toml()?
+        } else {
+            self.to_json()?
+        };
+        fs::write(path, data).map_err(|e| invalid(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Reads a snapshot written by `save`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Snapshot> {
+        let path = path.as_ref();
+        let data =
+            fs::read_to_string(path).map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+        if is_toml(path) {
+            Self::from_toml(&data)
+        } else {
+            Self::from_json(&data)
+        }
+    }
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "toml")
+}
+
+impl ClusterManager {
+    pub fn snapshot(&self) -> Snapshot {
+        let nodes = self
+            .node_names()
+            .into_iter()
+            .map(|name| {
+                let node = &self.nodes[name];
+                let mut vnodes: Vec<u64> = node.vnode_set.iter().cloned().collect();
+                vnodes.sort_unstable();
+                NodeSnapshot {
+                    name: node.name.clone(),
+                    weight: node.weight,
+                    region: node.location.region.clone(),
+                    zone: node.location.zone.clone(),
+                    rack: node.location.rack.clone(),
+                    host: node.location.host.clone(),
+                    vnodes,
+                }
+            })
+            .collect();
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            seed: self.seed,
+            rng_draws: self.rng.draws,
+            replica_num: self.replica_num,
+            roles: if self.roles == Role::layout(self.replica_num) {
+                Vec::new()
+            } else {
+                self.roles.clone()
+            },
+            tolerance: self.tolerance,
+            load_epsilon: self.load_epsilon,
+            rule: self.rule.clone(),
+            hash_algorithm: self.hash_algorithm,
+            vnode_num: self.max_vnode_id,
+            nodes,
+            slots: self.slot_vnodes.clone(),
+        }
+    }
+
+    /// Rebuilds a cluster from `snapshot`, checking that it is consistent and
+    /// that its replicas keep to the placement rule.
+    ///
+    /// The random generator resumes where it stopped, so later placements
+    /// match those of the cluster that was saved.
+    pub fn from_snapshot(snapshot: &Snapshot) -> Result<ClusterManager> {
+        if snapshot.version == 0 || snapshot.version > SNAPSHOT_VERSION {
+            return Err(Error::UnsupportedSnapshotVersion(snapshot.version));
+        }
+        let mut cm = ClusterManager::with_seed(snapshot.seed);
+        cm.rng = PlacementRng::new(snapshot.seed, snapshot.rng_draws);
+        cm.tolerance = snapshot.tolerance;
+        cm.set_load_epsilon(snapshot.load_epsilon)?;
+        cm.rule = snapshot.rule.clone();
+        cm.hash_algorithm = snapshot.hash_algorithm;
+        if snapshot.roles.is_empty() {
+            cm.set_replica_num(snapshot.replica_num)?;
+        } else if snapshot.roles.len() as u64 != snapshot.replica_num {
+            return Err(invalid(format!(
+                "{} roles for {} replicas",
+                snapshot.roles.len(),
+                snapshot.replica_num
+            )));
+        } else {
+            cm.set_roles(&snapshot.roles)?;
+        }
+        if snapshot.vnode_num > 0 {
+            cm.init_vnodes(snapshot.vnode_num)?;
+        }
+
+        for node_snapshot in &snapshot.nodes {
+            if node_snapshot.weight == 0 {
+                return Err(Error::InvalidWeight(node_snapshot.weight));
+            }
+            if cm.nodes.contains_key(&node_snapshot.name) {
+                return Err(Error::DuplicateNode(node_snapshot.name.clone()));
+            }
+            let mut node = Node::new(&node_snapshot.name);
+            node.weight = node_snapshot.weight;
+            node.location = Location {
+                region: node_snapshot.region.clone(),
+                zone: node_snapshot.zone.clone(),
+                rack: node_snapshot.rack.clone(),
+                host: node_snapshot.host.clone(),
+            };
+            for vnode_id in &node_snapshot.vnodes {
+                let vnode: &mut VNode = cm
+                    .vnodes
+                    .get_mut(*vnode_id as usize)
+                    .ok_or(Error::VNodeNotFound(*vnode_id))?;
+                if let Some(owner) = &vnode.node_name {
+                    return Err(invalid(format!(
+                        "vnode {} owned by both {} and {}",
+                        vnode_id, owner, node.name
+                    )));
+                }
+                vnode.assign(Some(node.name.clone()));
+                node.pickup_vnode(*vnode_id);
+            }
+            cm.nodes.insert(node.name.clone(), node);
+        }
+
+        cm.max_slot_id = snapshot.slots.len() as u64;
+        for (slot_id, vnode_ids) in snapshot.slots.iter().enumerate() {
+            if vnode_ids.len() as u64 != cm.replica_num {
+                return Err(invalid(format!(
+                    "slot {} has {} replicas, expected {}",
+                    slot_id,
+                    vnode_ids.len(),
+                    cm.replica_num
+                )));
+            }
+            let distinct: HashSet<&u64> = vnode_ids.iter().collect();
+            if distinct.len() != vnode_ids.len() {
+                return Err(invalid(format!(
+                    "slot {} lists a vnode more than once",
+                    slot_id
+                )));
+            }
+            for (vnode_id, role) in vnode_ids.iter().zip(cm.roles.clone()) {
+                cm.vnodes
+                    .get_mut(*vnode_id as usize)
+                    .ok_or(Error::VNodeNotFound(*vnode_id))?
+                    .slots
+                    .push(Slot {
+                        id: slot_id as u64,
+                        role,
+                    });
+            }
+        }
+        cm.slot_vnodes = snapshot.slots.clone();
+
+        // a loaded cluster keeps to its placement rule as `allocate` does
+        if cm.vnodes.iter().any(|vnode| vnode.node_name.is_some()) {
+            cm.check_domains()?;
+            if let Some(v) = cm.validate_placement().first() {
+                return Err(invalid(format!(
+                    "slot {} has vnodes {:?} in {} {}, breaking rule {}",
+                    v.slot_id, v.vnode_ids, v.level, v.domain, cm.rule
+                )));
+            }
+        }
+        Ok(cm)
+    }
+}
+
+/// Returns, per vnode, the owner in `old` and `new` where they differ.
+pub fn diff_snapshots(
+    old: &Snapshot,
+    new: &Snapshot,
+) -> Vec<(u64, Option<String>, Option<String>)> {
+    let owners = |snapshot: &Snapshot| -> HashMap<u64, String> {
+        let mut owners = HashMap::new();
+        for node in &snapshot.nodes {
+            for vnode_id in &node.vnodes {
+                owners.insert(*vnode_id, node.name.clone());
+            }
+        }
+        owners
+    };
+    let (old_owners, new_owners) = (owners(old), owners(new));
+    let vnode_num = old.vnode_num.max(new.vnode_num);
+    (0..vnode_num)
+        .filter_map(|vnode_id| {
+            let from = old_owners.get(&vnode_id).cloned();
+            let to = new_owners.get(&vnode_id).cloned();
+            if from != to {
+                Some((vnode_id, from, to))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_cluster() -> ClusterManager {
+        let mut cm = ClusterManager::with_seed(9);
+        cm.init_vnodes(256).unwrap();
+        cm.init_slots(16).unwrap();
+        cm.add_node("a").unwrap();
+        cm.set_rack("a", "r1").unwrap();
+        cm.allocate(&["b", "c", "d"]).unwrap();
+        cm.set_weight("b", 2).unwrap();
+        cm
+    }
+
+    fn assert_same(cm1: &ClusterManager, cm2: &ClusterManager) {
+        assert_eq!(cm1.snapshot(), cm2.snapshot());
+        for vnode in cm1.vnodes() {
+            let other = cm2.vnode(vnode.id()).unwrap();
+            assert_eq!(vnode.node_name(), other.node_name());
+            assert_eq!(vnode.slots(), other.slots());
+        }
+        assert_eq!(cm1.lookup(b"foo"), cm2.lookup(b"foo"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let cm = new_cluster();
+        let snapshot = cm.snapshot();
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        assert_eq!(snapshot.nodes[0].rack.as_deref(), Some("r1"));
+
+        let json = Snapshot::from_json(&snapshot.to_json().unwrap()).unwrap();
+        assert_same(&cm, &ClusterManager::from_snapshot(&json).unwrap());
+        let toml = Snapshot::from_toml(&snapshot.to_toml().unwrap()).unwrap();
+        assert_same(&cm, &ClusterManager::from_snapshot(&toml).unwrap());
+
+        let dir = std::env::temp_dir();
+        for ext in &["json", "toml"] {
+            let path = dir.join(format!(
+                "consistent-hash-test-{}-round-trip.{}",
+                std::process::id(),
+                ext
+            ));
+            snapshot.save(&path).unwrap();
+            assert_eq!(Snapshot::load(&path).unwrap(), snapshot);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_resumed_rng() {
+        let mut cm = new_cluster();
+        cm.set_load_epsilon(Some(0.25)).unwrap();
+        let snapshot = cm.snapshot();
+        assert!(snapshot.rng_draws > 0);
+        let json = Snapshot::from_json(&snapshot.to_json().unwrap()).unwrap();
+        let mut loaded = ClusterManager::from_snapshot(&json).unwrap();
+        assert_eq!(loaded.load_epsilon(), Some(0.25));
+        assert_eq!(cm.scale("e").unwrap(), loaded.scale("e").unwrap());
+        assert_eq!(
+            cm.decommission("a").unwrap(),
+            loaded.decommission("a").unwrap()
+        );
+        assert_same(&cm, &loaded);
+    }
+
+    #[test]
+    fn test_invalid_snapshot() {
+        let cm = new_cluster();
+        let mut snapshot = cm.snapshot();
+        snapshot.version = SNAPSHOT_VERSION + 1;
+        assert_eq!(
+            ClusterManager::from_snapshot(&snapshot).unwrap_err(),
+            Error::UnsupportedSnapshotVersion(SNAPSHOT_VERSION + 1)
+        );
+
+        let mut snapshot = cm.snapshot();
+        let vnode_id = snapshot.nodes[0].vnodes[0];
+        snapshot.nodes[1].vnodes.push(vnode_id);
+        assert!(ClusterManager::from_snapshot(&snapshot).is_err());
+
+        let mut snapshot = cm.snapshot();
+        snapshot.slots[0].pop();
+        assert!(ClusterManager::from_snapshot(&snapshot).is_err());
+
+        let mut snapshot = cm.snapshot();
+        snapshot.slots[0][1] = snapshot.slots[0][0];
+        assert!(matches!(
+            ClusterManager::from_snapshot(&snapshot),
+            Err(Error::InvalidSnapshot(_))
+        ));
+
+        // two replicas of slot 0 on vnodes of the same node
+        let mut snapshot = cm.snapshot();
+        let owner = |snapshot: &Snapshot, vnode_id: u64| {
+            snapshot
+                .nodes
+                .iter()
+                .position(|node| node.vnodes.contains(&vnode_id))
+                .unwrap()
+        };
+        let (first, second) = (snapshot.slots[0][0], snapshot.slots[0][1]);
+        let (from, to) = (owner(&snapshot, second), owner(&snapshot, first));
+        snapshot.nodes[from].vnodes.retain(|v| *v != second);
+        snapshot.nodes[to].vnodes.push(second);
+        snapshot.nodes[to].vnodes.sort_unstable();
+        assert!(matches!(
+            ClusterManager::from_snapshot(&snapshot),
+            Err(Error::InvalidSnapshot(_))
+        ));
+
+        let mut snapshot = cm.snapshot();
+        snapshot.load_epsilon = Some(-1.0);
+        assert!(matches!(
+            ClusterManager::from_snapshot(&snapshot),
+            Err(Error::InvalidEpsilon(_))
+        ));
+        assert!(Snapshot::from_json("{}").is_err());
+    }
+
+    #[test]
+    fn test_roles() {
+        let mut cm = new_cluster();
+        assert!(cm.snapshot().roles.is_empty());
+        cm.set_roles(&[Role::Primary, Role::Learner, Role::Witness])
+            .unwrap();
+        let snapshot = cm.snapshot();
+        assert_eq!(snapshot.roles.len(), 3);
+        let other = ClusterManager::from_snapshot(&snapshot).unwrap();
+        assert_eq!(other.roles(), cm.roles());
+        assert_same(&cm, &other);
+
+        let mut snapshot = cm.snapshot();
+        snapshot.roles.pop();
+        assert!(ClusterManager::from_snapshot(&snapshot).is_err());
+    }
+
+    #[test]
+    fn test_diff_snapshots() {
+        let mut cm = new_cluster();
+        let old = cm.snapshot();
+        let plan = cm.scale("e").unwrap();
+        let diff = diff_snapshots(&old, &cm.snapshot());
+        assert_eq!(diff.len(), plan.len());
+        for (step, (vnode_id, from, to)) in plan.steps.iter().zip(&diff) {
+            assert_eq!(step.vnode_id, *vnode_id);
+            assert_eq!(Some(&step.from), from.as_ref());
+            assert_eq!(Some(&step.to), to.as_ref());
+        }
+    }
+}
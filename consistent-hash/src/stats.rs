@@ -0,0 +1,369 @@
+use crate::cluster::ClusterManager;
+use crate::migration::MigrationPlan;
+use crate::placement::DomainLevel;
+use crate::role::Role;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+/// What one node holds, next to what its weight entitles it to.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NodeStats {
+    pub name: String,
+    pub weight: u64,
+    pub vnodes: usize,
+    pub primary_slots: usize,
+    pub secondary_slots: usize,
+    /// Learner, witness and arbiter replicas.
+    pub non_voting_slots: usize,
+    /// Fraction of vnodes the weight entitles the node to.
+    pub expected_share: f64,
+    pub vnode_share: f64,
+    pub slot_share: f64,
+}
+
+/// How evenly vnodes and slot replicas are spread, relative to weights.
+///
+/// Loads are normalized so that a node holding exactly its weighted share
+/// has load 1.0; a perfect layout has zero deviation and a ratio of 1.0.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BalanceStats {
+    pub nodes: Vec<NodeStats>,
+    pub vnode_load_stddev: f64,
+    pub vnode_load_max_min_ratio: f64,
+    pub slot_load_stddev: f64,
+    pub slot_load_max_min_ratio: f64,
+}
+
+/// What the nodes of one failure domain hold together.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DomainStats {
+    pub domain: String,
+    pub nodes: usize,
+    pub weight: u64,
+    pub vnodes: usize,
+    pub primary_slots: usize,
+    pub secondary_slots: usize,
+    pub non_voting_slots: usize,
+    pub expected_share: f64,
+    pub vnode_share: f64,
+}
+
+/// How much one topology change moved, against the least it had to move.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MovementStats {
+    pub vnodes_moved: usize,
+    /// Fewest vnodes any plan could move to reach the weighted shares.
+    pub vnodes_minimum: usize,
+    pub vnode_fraction: f64,
+    pub vnode_minimum_fraction: f64,
+    pub slot_replicas_moved: usize,
+    pub slot_replica_fraction: f64,
+    /// Fraction of slots whose primary moved, i.e. of keys whose reads and
+    /// writes go to another node.
+    pub key_fraction: f64,
+}
+
+fn ratio(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    part as f64 / total as f64
+}
+
+/// Returns the standard deviation and the max/min ratio of `loads`.
+pub(crate) fn spread(loads: &[f64]) -> (f64, f64) {
+    if loads.is_empty() {
+        return (0.0, 1.0);
+    }
+    let n = loads.len() as f64;
+    let mean = loads.iter().sum::<f64>() / n;
+    let variance = loads.iter().map(|l| (l - mean) * (l - mean)).sum::<f64>() / n;
+    let max = loads.iter().cloned().fold(f64::MIN, f64::max);
+    let min = loads.iter().cloned().fold(f64::MAX, f64::min);
+    let max_min_ratio = if min > 0.0 { max / min } else { f64::INFINITY };
+    (variance.sqrt(), max_min_ratio)
+}
+
+impl ClusterManager {
+    pub fn balance_stats(&self) -> BalanceStats {
+        let slot_copies: usize = self.vnodes.iter().map(|v| v.slots.len()).sum();
+        let mut nodes = Vec::new();
+        for name in self.node_names() {
+            let node = &self.nodes[name];
+            let (mut primary_slots, mut secondary_slots, mut non_voting_slots) = (0, 0, 0);
+            for vnode_id in &node.vnode_set {
+                for slot in &self.vnodes[*vnode_id as usize].slots {
+                    match slot.role {
+                        Role::Primary => primary_slots += 1,
+                        Role::Secondary => secondary_slots += 1,
+                        _ => non_voting_slots += 1,
+                    }
+                }
+            }
+            nodes.push(NodeStats {
+                name: node.name.clone(),
+                weight: node.weight,
+                vnodes: node.vnode_set.len(),
+                primary_slots,
+                secondary_slots,
+                non_voting_slots,
+                expected_share: self.expected_share(name).unwrap_or(0.0),
+                vnode_share: ratio(node.vnode_set.len(), self.vnodes.len()),
+                slot_share: ratio(
+                    primary_slots + secondary_slots + non_voting_slots,
+                    slot_copies,
+                ),
+            });
+        }
+
+        let loads = |share: fn(&NodeStats) -> f64| -> Vec<f64> {
+            nodes.iter().map(|n| share(n) / n.expected_share).collect()
+        };
+        let (vnode_load_stddev, vnode_load_max_min_ratio) = spread(&loads(|n| n.vnode_share));
+        let (slot_load_stddev, slot_load_max_min_ratio) = spread(&loads(|n| n.slot_share));
+        BalanceStats {
+            nodes,
+            vnode_load_stddev,
+            vnode_load_max_min_ratio,
+            slot_load_stddev,
+            slot_load_max_min_ratio,
+        }
+    }
+
+    /// Sums `balance_stats` per domain at `level`, sorted by domain.
+    pub fn domain_stats(&self, level: DomainLevel) -> Vec<DomainStats> {
+        let mut domains: BTreeMap<String, DomainStats> = BTreeMap::new();
+        for node in self.balance_stats().nodes {
+            let domain = self.domain_of(&node.name, level).to_string();
+            let stats = domains
+                .entry(domain.clone())
+                .or_insert_with(|| DomainStats {
+                    domain,
+                    nodes: 0,
+                    weight: 0,
+                    vnodes: 0,
+                    primary_slots: 0,
+                    secondary_slots: 0,
+                    non_voting_slots: 0,
+                    expected_share: 0.0,
+                    vnode_share: 0.0,
+                });
+            stats.nodes += 1;
+            stats.weight += node.weight;
+            stats.vnodes += node.vnodes;
+            stats.primary_slots += node.primary_slots;
+            stats.secondary_slots += node.secondary_slots;
+            stats.non_voting_slots += node.non_voting_slots;
+            stats.expected_share += node.expected_share;
+            stats.vnode_share += node.vnode_share;
+        }
+        domains.into_values().collect()
+    }
+
+    /// Measures `plan` once it has been applied to this cluster.
+    ///
+    /// The minimum counts the vnodes that nodes below their new weighted
+    /// quota must receive, given what they owned before the plan.
+    pub fn movement_stats(&self, plan: &MigrationPlan) -> MovementStats {
+        let mut before: HashMap<&str, i64> = HashMap::new();
+        for name in self.node_names() {
+            before.insert(name, self.nodes[name].vnode_set.len() as i64);
+        }
+        for step in &plan.steps {
+            *before.entry(step.to.as_str()).or_default() -= 1;
+            *before.entry(step.from.as_str()).or_default() += 1;
+        }
+
+        let names: Vec<String> = self.node_names().into_iter().map(String::from).collect();
+        let quotas = self.weighted_quotas(&names, self.max_vnode_id);
+        let vnodes_minimum: i64 = names
+            .iter()
+            .zip(quotas)
+            .map(|(name, quota)| (quota as i64 - before[name.as_str()]).max(0))
+            .sum();
+
+        let slot_copies: usize = self.vnodes.iter().map(|v| v.slots.len()).sum();
+        let moved_primaries: HashSet<u64> = plan
+            .steps
+            .iter()
+            .flat_map(|step| step.slots.iter())
+            .filter(|slot| slot.role == Role::Primary)
+            .map(|slot| slot.id)
+            .collect();
+        MovementStats {
+            vnodes_moved: plan.len(),
+            vnodes_minimum: vnodes_minimum as usize,
+            vnode_fraction: ratio(plan.len(), self.vnodes.len()),
+            vnode_minimum_fraction: ratio(vnodes_minimum as usize, self.vnodes.len()),
+            slot_replicas_moved: plan.slot_moves(),
+            slot_replica_fraction: ratio(plan.slot_moves(), slot_copies),
+            key_fraction: ratio(moved_primaries.len(), self.max_slot_id as usize),
+        }
+    }
+}
+
+impl fmt::Display for BalanceStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<12} {:>6} {:>8} {:>9} {:>8} {:>8} {:>10} {:>10} {:>8}",
+            "node",
+            "weight",
+            "vnodes",
+            "expected",
+            "vnode%",
+            "primary",
+            "secondary",
+            "non-voting",
+            "slot%"
+        )?;
+        for node in &self.nodes {
+            writeln!(
+                f,
+                "{:<12} {:>6} {:>8} {:>8.2}% {:>7.2}% {:>8} {:>10} {:>10} {:>7.2}%",
+                node.name,
+                node.weight,
+                node.vnodes,
+                node.expected_share * 100.0,
+                node.vnode_share * 100.0,
+                node.primary_slots,
+                node.secondary_slots,
+                node.non_voting_slots,
+                node.slot_share * 100.0
+            )?;
+        }
+        writeln!(
+            f,
+            "vnode load: stddev {:.4}, max/min {:.4}",
+            self.vnode_load_stddev, self.vnode_load_max_min_ratio
+        )?;
+        writeln!(
+            f,
+            "slot load: stddev {:.4}, max/min {:.4}",
+            self.slot_load_stddev, self.slot_load_max_min_ratio
+        )
+    }
+}
+
+impl fmt::Display for DomainStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<12} {:>6} {:>6} {:>8} {:>8.2}% {:>7.2}% {:>8} {:>10} {:>10}",
+            self.domain,
+            self.nodes,
+            self.weight,
+            self.vnodes,
+            self.expected_share * 100.0,
+            self.vnode_share * 100.0,
+            self.primary_slots,
+            self.secondary_slots,
+            self.non_voting_slots
+        )
+    }
+}
+
+impl DomainStats {
+    /// Column headers matching the `Display` output.
+    pub fn header() -> String {
+        format!(
+            "{:<12} {:>6} {:>6} {:>8} {:>9} {:>8} {:>8} {:>10} {:>10}",
+            "domain",
+            "nodes",
+            "weight",
+            "vnodes",
+            "expected",
+            "vnode%",
+            "primary",
+            "secondary",
+            "non-voting"
+        )
+    }
+}
+
+impl fmt::Display for MovementStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "vnodes moved: {} ({:.2}%), minimum {} ({:.2}%)",
+            self.vnodes_moved,
+            self.vnode_fraction * 100.0,
+            self.vnodes_minimum,
+            self.vnode_minimum_fraction * 100.0
+        )?;
+        writeln!(
+            f,
+            "slot replicas moved: {} ({:.2}%), keys moved: {:.2}%",
+            self.slot_replicas_moved,
+            self.slot_replica_fraction * 100.0,
+            self.key_fraction * 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_cluster() -> ClusterManager {
+        let mut cm = ClusterManager::with_seed(13);
+        cm.init_vnodes(4096).unwrap();
+        cm.init_slots(64).unwrap();
+        cm.allocate(&["a", "b", "c", "d"]).unwrap();
+        cm
+    }
+
+    #[test]
+    fn test_balance_stats() {
+        let mut cm = new_cluster();
+        let stats = cm.balance_stats();
+        assert_eq!(stats.nodes.len(), 4);
+        assert_eq!(stats.nodes.iter().map(|n| n.vnodes).sum::<usize>(), 4096);
+        assert!(stats.vnode_load_stddev < 1e-9);
+        assert!((stats.vnode_load_max_min_ratio - 1.0).abs() < 1e-9);
+
+        cm.set_weight("a", 2).unwrap();
+        let stats = cm.balance_stats();
+        assert_eq!(stats.nodes[0].expected_share, 0.4);
+        assert!(stats.vnode_load_max_min_ratio < 1.01);
+        assert!(stats.to_string().contains("vnode load"));
+    }
+
+    #[test]
+    fn test_domain_stats() {
+        let mut cm = new_cluster();
+        for (name, rack) in &[("a", "r1"), ("b", "r1"), ("c", "r2")] {
+            cm.set_rack(name, rack).unwrap();
+        }
+        let domains = cm.domain_stats(DomainLevel::Rack);
+        let names: Vec<&str> = domains.iter().map(|d| d.domain.as_str()).collect();
+        assert_eq!(names, vec!["d", "r1", "r2"]);
+        assert_eq!(domains[1].nodes, 2);
+        assert_eq!(domains[1].vnodes, 2048);
+        assert_eq!(domains[1].expected_share, 0.5);
+        let replicas: usize = domains
+            .iter()
+            .map(|d| d.primary_slots + d.secondary_slots)
+            .sum();
+        assert_eq!(replicas, 64 * 3);
+    }
+
+    #[test]
+    fn test_movement_stats() {
+        let mut cm = new_cluster();
+        let plan = cm.scale("e").unwrap();
+        let stats = cm.movement_stats(&plan);
+        assert_eq!(stats.vnodes_moved, plan.len());
+        assert!(stats.vnodes_minimum <= stats.vnodes_moved);
+        assert!(stats.vnodes_moved - stats.vnodes_minimum <= 1);
+        assert!((stats.vnode_minimum_fraction - 0.2).abs() < 0.001);
+        assert!(stats.key_fraction <= stats.slot_replica_fraction * 3.0);
+
+        let plan = cm.decommission("a").unwrap();
+        let stats = cm.movement_stats(&plan);
+        assert!(stats.vnodes_minimum <= stats.vnodes_moved);
+        assert_eq!(stats.vnodes_moved, plan.len());
+        assert!(serde_json::to_string(&stats).is_ok());
+    }
+}
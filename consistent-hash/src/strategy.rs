@@ -0,0 +1,846 @@
+use crate::cluster::ClusterManager;
+use crate::error::{Error, Result};
+use crate::hash::{HashAlgorithm, PlacementHasher};
+use crate::load::check_epsilon;
+use crate::stats::spread;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Maps slots to an ordered list of distinct nodes, primary first.
+///
+/// `build` is called with the full node list every time the membership
+/// changes, so stateful strategies can move as little as they are able to.
+pub trait PlacementStrategy {
+    fn name(&self) -> &'static str;
+
+    /// Takes the new membership as `(name, weight)` pairs.
+    fn build(&mut self, nodes: &[(String, u64)]) -> Result<()>;
+
+    /// Returns up to `replica_num` distinct nodes for `slot_id`.
+    fn place(&self, slot_id: u64, replica_num: usize) -> Vec<String>;
+
+    /// Fraction of replicas the last `build` placed away from their first
+    /// choice to cap node load, for strategies that do.
+    fn overflow_fraction(&self) -> Option<f64> {
+        None
+    }
+}
+
+fn hash_with(hasher: HashAlgorithm, name: &[u8], n: u64) -> u64 {
+    let mut data = name.to_vec();
+    data.extend_from_slice(&n.to_le_bytes());
+    hasher.hash(&data)
+}
+
+/// Classic consistent hashing: every node owns `points * weight` points on
+/// a ring and a slot goes to the nodes following its hash clockwise.
+#[derive(Debug, Clone)]
+pub struct RingStrategy {
+    points: u64,
+    hasher: HashAlgorithm,
+    ring: Vec<(u64, String)>,
+}
+
+impl RingStrategy {
+    pub fn new(points: u64) -> RingStrategy {
+        RingStrategy {
+            points,
+            hasher: HashAlgorithm::default(),
+            ring: Vec::new(),
+        }
+    }
+}
+
+impl PlacementStrategy for RingStrategy {
+    fn name(&self) -> &'static str {
+        "ring"
+    }
+
+    fn build(&mut self, nodes: &[(String, u64)]) -> Result<()> {
+        self.ring.clear();
+        for (name, weight) in nodes {
+            for i in 0..self.points * weight {
+                let point = hash_with(self.hasher, name.as_bytes(), i);
+                self.ring.push((point, name.clone()));
+            }
+        }
+        self.ring.sort_unstable();
+        Ok(())
+    }
+
+    fn place(&self, slot_id: u64, replica_num: usize) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        if self.ring.is_empty() {
+            return names;
+        }
+        let hc = self.hasher.hash(&slot_id.to_le_bytes());
+        let start = self.ring.partition_point(|(point, _)| *point < hc);
+        for k in 0..self.ring.len() {
+            let name = &self.ring[(start + k) % self.ring.len()].1;
+            if !names.contains(name) {
+                names.push(name.clone());
+                if names.len() == replica_num {
+                    break;
+                }
+            }
+        }
+        names
+    }
+}
+
+/// Consistent hashing with bounded loads (Mirrokni, Thorup and
+/// Zadimoghaddam): slots walk the ring as in `RingStrategy`, but a node
+/// already holding `1 + epsilon` times its weighted share of the replicas
+/// in some position, primaries say, is passed over for that position.
+///
+/// `build` places slots `0..slot_num` in order, so `place` covers no other
+/// slots and at most `replica_num` replicas.
+#[derive(Debug, Clone)]
+pub struct BoundedLoadStrategy {
+    ring: RingStrategy,
+    slot_num: u64,
+    replica_num: usize,
+    epsilon: f64,
+    slot_loads: Vec<u64>,
+    layout: Vec<Vec<String>>,
+    overflowed: usize,
+}
+
+impl BoundedLoadStrategy {
+    /// Fails unless `epsilon` is finite and above zero.
+    pub fn new(
+        points: u64,
+        slot_num: u64,
+        replica_num: usize,
+        epsilon: f64,
+    ) -> Result<BoundedLoadStrategy> {
+        check_epsilon(epsilon)?;
+        Ok(BoundedLoadStrategy {
+            ring: RingStrategy::new(points),
+            slot_num,
+            replica_num,
+            epsilon,
+            slot_loads: Vec::new(),
+            layout: Vec::new(),
+            overflowed: 0,
+        })
+    }
+
+    /// Weighs every slot by the load it carries, such as the requests of
+    /// the tenant living in it, instead of counting each slot once. Slots
+    /// past the end of `loads` count once.
+    pub fn with_slot_loads(mut self, loads: Vec<u64>) -> BoundedLoadStrategy {
+        self.slot_loads = loads;
+        self
+    }
+
+    pub fn epsilon(&self) -> f64 {
+        self.epsilon
+    }
+
+    fn slot_load(&self, slot_id: u64) -> u64 {
+        self.slot_loads.get(slot_id as usize).cloned().unwrap_or(1)
+    }
+}
+
+impl PlacementStrategy for BoundedLoadStrategy {
+    fn name(&self) -> &'static str {
+        "bounded-load"
+    }
+
+    fn build(&mut self, nodes: &[(String, u64)]) -> Result<()> {
+        self.ring.build(nodes)?;
+        self.layout.clear();
+        self.overflowed = 0;
+        let replica_num = self.replica_num.min(nodes.len());
+        let total: u64 = (0..self.slot_num)
+            .map(|slot_id| self.slot_load(slot_id))
+            .sum();
+        let total = total as f64;
+        let weight_sum: u64 = nodes.iter().map(|(_, w)| w).sum();
+        let capacity: HashMap<&str, f64> = nodes
+            .iter()
+            .map(|(name, weight)| {
+                let share = *weight as f64 / weight_sum as f64;
+                (name.as_str(), ((1.0 + self.epsilon) * total * share).ceil())
+            })
+            .collect();
+        let mut loads: HashMap<(usize, &str), u64> = HashMap::new();
+
+        let ring = &self.ring.ring;
+        for slot_id in 0..self.slot_num {
+            let cost = self.slot_load(slot_id);
+            let hc = self.ring.hasher.hash(&slot_id.to_le_bytes());
+            let start = ring.partition_point(|(point, _)| *point < hc);
+            let mut names: Vec<&str> = Vec::new();
+            while names.len() < replica_num {
+                let r = names.len();
+                let mut passed_over = false;
+                let fits = (0..ring.len())
+                    .map(|k| ring[(start + k) % ring.len()].1.as_str())
+                    .filter(|name| !names.contains(name))
+                    .find(|name| {
+                        let load = loads.get(&(r, *name)).cloned().unwrap_or(0);
+                        let fits = (load + cost) as f64 <= capacity[name];
+                        passed_over |= !fits;
+                        fits
+                    });
+                // a heavy slot may fit nowhere; it goes where it hurts least
+                let name = fits.unwrap_or_else(|| {
+                    nodes
+                        .iter()
+                        .map(|(name, _)| name.as_str())
+                        .filter(|name| !names.contains(name))
+                        .min_by(|a, b| {
+                            let load = |n: &&str| {
+                                loads.get(&(r, *n)).cloned().unwrap_or(0) as f64 / capacity[n]
+                            };
+                            load(a).total_cmp(&load(b)).then_with(|| a.cmp(b))
+                        })
+                        .unwrap()
+                });
+                if passed_over {
+                    self.overflowed += 1;
+                }
+                *loads.entry((r, name)).or_default() += cost;
+                names.push(name);
+            }
+            self.layout
+                .push(names.into_iter().map(String::from).collect());
+        }
+        Ok(())
+    }
+
+    fn place(&self, slot_id: u64, replica_num: usize) -> Vec<String> {
+        match self.layout.get(slot_id as usize) {
+            Some(names) => names.iter().take(replica_num).cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn overflow_fraction(&self) -> Option<f64> {
+        let placed: usize = self.layout.iter().map(Vec::len).sum();
+        if placed == 0 {
+            return Some(0.0);
+        }
+        Some(self.overflowed as f64 / placed as f64)
+    }
+}
+
+/// Jump Consistent Hash (Lamping and Veach). Buckets are numbered in the
+/// order nodes first appeared, so only appending nodes moves the minimum;
+/// weights are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct JumpStrategy {
+    hasher: HashAlgorithm,
+    buckets: Vec<String>,
+}
+
+fn jump_consistent_hash(mut key: u64, bucket_num: usize) -> usize {
+    let (mut b, mut j): (i64, i64) = (-1, 0);
+    while j < bucket_num as i64 {
+        b = j;
+        key = key.wrapping_mul(2_862_933_555_777_941_757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    b as usize
+}
+
+impl JumpStrategy {
+    pub fn new() -> JumpStrategy {
+        JumpStrategy::default()
+    }
+}
+
+impl PlacementStrategy for JumpStrategy {
+    fn name(&self) -> &'static str {
+        "jump"
+    }
+
+    fn build(&mut self, nodes: &[(String, u64)]) -> Result<()> {
+        let names: HashSet<&String> = nodes.iter().map(|(name, _)| name).collect();
+        self.buckets.retain(|name| names.contains(name));
+        for (name, _) in nodes {
+            if !self.buckets.contains(name) {
+                self.buckets.push(name.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn place(&self, slot_id: u64, replica_num: usize) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        let want = replica_num.min(self.buckets.len());
+        // later replicas rehash the slot until they land on a new bucket
+        let mut attempt = 0;
+        while names.len() < want {
+            let key = hash_with(self.hasher, &slot_id.to_le_bytes(), attempt);
+            let name = &self.buckets[jump_consistent_hash(key, self.buckets.len())];
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+            attempt += 1;
+        }
+        names
+    }
+}
+
+/// Rendezvous (highest random weight) hashing with logarithmic weighting.
+#[derive(Debug, Clone, Default)]
+pub struct RendezvousStrategy {
+    hasher: HashAlgorithm,
+    nodes: Vec<(String, u64)>,
+}
+
+impl RendezvousStrategy {
+    pub fn new() -> RendezvousStrategy {
+        RendezvousStrategy::default()
+    }
+}
+
+impl PlacementStrategy for RendezvousStrategy {
+    fn name(&self) -> &'static str {
+        "rendezvous"
+    }
+
+    fn build(&mut self, nodes: &[(String, u64)]) -> Result<()> {
+        self.nodes = nodes.to_vec();
+        Ok(())
+    }
+
+    fn place(&self, slot_id: u64, replica_num: usize) -> Vec<String> {
+        let mut scores: Vec<(f64, &String)> = self
+            .nodes
+            .iter()
+            .map(|(name, weight)| {
+                let hc = hash_with(self.hasher, name.as_bytes(), slot_id);
+                let u = ((hc >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+                (*weight as f64 / -u.ln(), name)
+            })
+            .collect();
+        scores.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+        scores
+            .into_iter()
+            .take(replica_num)
+            .map(|(_, name)| name.clone())
+            .collect()
+    }
+}
+
+/// Maglev hashing: nodes take turns filling a prime-sized lookup table in
+/// their own permutation order; weights are ignored.
+#[derive(Debug, Clone)]
+pub struct MaglevStrategy {
+    table_size: u64,
+    hasher: HashAlgorithm,
+    table: Vec<String>,
+}
+
+fn is_prime(n: u64) -> bool {
+    n > 1
+        && (2..)
+            .take_while(|d| d * d <= n)
+            .all(|d| !n.is_multiple_of(d))
+}
+
+impl MaglevStrategy {
+    /// `table_size` must be a prime, so that every node's permutation
+    /// visits the whole table, and should be well above the node count.
+    pub fn new(table_size: u64) -> Result<MaglevStrategy> {
+        if !is_prime(table_size) {
+            return Err(Error::InvalidStrategy(format!(
+                "maglev table size {} is not a prime",
+                table_size
+            )));
+        }
+        Ok(MaglevStrategy {
+            table_size,
+            hasher: HashAlgorithm::default(),
+            table: Vec::new(),
+        })
+    }
+}
+
+impl PlacementStrategy for MaglevStrategy {
+    fn name(&self) -> &'static str {
+        "maglev"
+    }
+
+    fn build(&mut self, nodes: &[(String, u64)]) -> Result<()> {
+        self.table.clear();
+        if nodes.is_empty() {
+            return Ok(());
+        }
+        let size = self.table_size;
+        let permutations: Vec<(u64, u64)> = nodes
+            .iter()
+            .map(|(name, _)| {
+                let offset = hash_with(self.hasher, name.as_bytes(), 0) % size;
+                let skip = hash_with(self.hasher, name.as_bytes(), 1) % (size - 1) + 1;
+                (offset, skip)
+            })
+            .collect();
+        let mut entries: Vec<Option<usize>> = vec![None; size as usize];
+        let mut next = vec![0u64; nodes.len()];
+        let mut filled = 0;
+        'fill: loop {
+            for (i, (offset, skip)) in permutations.iter().enumerate() {
+                let mut c = (offset + next[i] * skip) % size;
+                while entries[c as usize].is_some() {
+                    next[i] += 1;
+                    c = (offset + next[i] * skip) % size;
+                }
+                entries[c as usize] = Some(i);
+                next[i] += 1;
+                filled += 1;
+                if filled == size {
+                    break 'fill;
+                }
+            }
+        }
+        self.table = entries
+            .into_iter()
+            .map(|i| nodes[i.unwrap()].0.clone())
+            .collect();
+        Ok(())
+    }
+
+    fn place(&self, slot_id: u64, replica_num: usize) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        if self.table.is_empty() {
+            return names;
+        }
+        let start = (self.hasher.hash(&slot_id.to_le_bytes()) % self.table_size) as usize;
+        for k in 0..self.table.len() {
+            let name = &self.table[(start + k) % self.table.len()];
+            if !names.contains(name) {
+                names.push(name.clone());
+                if names.len() == replica_num {
+                    break;
+                }
+            }
+        }
+        names
+    }
+}
+
+/// The replica-set scheme of consistent-hash-variant: slot `i` goes to the
+/// sorted node list rotated by `i`.
+#[derive(Debug, Clone, Default)]
+pub struct RoundRobinStrategy {
+    names: Vec<String>,
+}
+
+impl RoundRobinStrategy {
+    pub fn new() -> RoundRobinStrategy {
+        RoundRobinStrategy::default()
+    }
+}
+
+impl PlacementStrategy for RoundRobinStrategy {
+    fn name(&self) -> &'static str {
+        "round-robin"
+    }
+
+    fn build(&mut self, nodes: &[(String, u64)]) -> Result<()> {
+        self.names = nodes.iter().map(|(name, _)| name.clone()).collect();
+        self.names.sort_unstable();
+        Ok(())
+    }
+
+    fn place(&self, slot_id: u64, replica_num: usize) -> Vec<String> {
+        let n = self.names.len();
+        (0..replica_num.min(n))
+            .map(|r| self.names[(slot_id as usize + r) % n].clone())
+            .collect()
+    }
+}
+
+/// The vnode scheme of this crate: membership changes go through
+/// `allocate`, `scale`, `decommission` and `set_weight`.
+#[derive(Debug, Clone)]
+pub struct VNodeStrategy {
+    vnode_num: u64,
+    slot_num: u64,
+    replica_num: u64,
+    seed: u64,
+    cm: Option<ClusterManager>,
+}
+
+impl VNodeStrategy {
+    /// Slots passed to `place` must be below `slot_num`, and at most
+    /// `replica_num` replicas are placed.
+    pub fn new(vnode_num: u64, slot_num: u64, replica_num: u64, seed: u64) -> VNodeStrategy {
+        VNodeStrategy {
+            vnode_num,
+            slot_num,
+            replica_num,
+            seed,
+            cm: None,
+        }
+    }
+
+    pub fn cluster(&self) -> Option<&ClusterManager> {
+        self.cm.as_ref()
+    }
+}
+
+impl PlacementStrategy for VNodeStrategy {
+    fn name(&self) -> &'static str {
+        "vnode"
+    }
+
+    fn build(&mut self, nodes: &[(String, u64)]) -> Result<()> {
+        let cm = match &mut self.cm {
+            Some(cm) => cm,
+            None => {
+                let mut cm = ClusterManager::with_seed(self.seed);
+                cm.set_replica_num(self.replica_num)?;
+                cm.init_vnodes(self.vnode_num)?;
+                cm.init_slots(self.slot_num)?;
+                for (name, weight) in nodes {
+                    cm.add_node(name)?;
+                    cm.set_weight(name, *weight)?;
+                }
+                cm.allocate(&[])?;
+                self.cm = Some(cm);
+                return Ok(());
+            }
+        };
+
+        let weights: HashMap<&str, u64> = nodes.iter().map(|(n, w)| (n.as_str(), *w)).collect();
+        let removed: Vec<String> = cm
+            .node_names()
+            .into_iter()
+            .filter(|name| !weights.contains_key(name))
+            .map(String::from)
+            .collect();
+        for name in removed {
+            cm.decommission(&name)?;
+        }
+        for (name, weight) in nodes {
+            match cm.node(name).map(|node| node.weight()) {
+                Some(w) if w == *weight => {}
+                Some(_) => {
+                    cm.set_weight(name, *weight)?;
+                }
+                None => {
+                    cm.scale_weighted(name, *weight)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn place(&self, slot_id: u64, replica_num: usize) -> Vec<String> {
+        let cm = match &self.cm {
+            Some(cm) => cm,
+            None => return Vec::new(),
+        };
+        cm.slot_replicas(slot_id)
+            .unwrap_or_default()
+            .into_iter()
+            .take(replica_num)
+            .map(|replica| replica.node_name)
+            .collect()
+    }
+}
+
+/// Balance and movement of one strategy across one membership change.
+///
+/// Loads are normalized by weight as in `BalanceStats`; fractions are of
+/// all placed replicas, or of all slots for primaries.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StrategyReport {
+    pub strategy: String,
+    pub primary_load_stddev: f64,
+    pub primary_load_max_min_ratio: f64,
+    pub replica_load_stddev: f64,
+    pub replica_load_max_min_ratio: f64,
+    pub primary_moved_fraction: f64,
+    pub replica_moved_fraction: f64,
+    /// Fraction any strategy must move to follow the weights.
+    pub minimum_moved_fraction: f64,
+    /// Fraction of replicas placed past a full node, for bounded loads.
+    pub overflow_fraction: Option<f64>,
+}
+
+fn layout(strategy: &dyn PlacementStrategy, slot_num: u64, replica_num: usize) -> Vec<Vec<String>> {
+    (0..slot_num)
+        .map(|slot_id| strategy.place(slot_id, replica_num))
+        .collect()
+}
+
+fn shares(nodes: &[(String, u64)]) -> HashMap<&str, f64> {
+    let total: u64 = nodes.iter().map(|(_, w)| w).sum();
+    nodes
+        .iter()
+        .map(|(n, w)| (n.as_str(), *w as f64 / total as f64))
+        .collect()
+}
+
+/// Builds `strategy` for `before`, then for `after`, and measures the
+/// layout reached and what moved in between.
+pub fn compare_strategy(
+    strategy: &mut dyn PlacementStrategy,
+    before: &[(String, u64)],
+    after: &[(String, u64)],
+    slot_num: u64,
+    replica_num: usize,
+) -> Result<StrategyReport> {
+    if slot_num == 0 {
+        return Err(Error::InvalidSlotNum(slot_num));
+    }
+    strategy.build(before)?;
+    let old = layout(strategy, slot_num, replica_num);
+    strategy.build(after)?;
+    let new = layout(strategy, slot_num, replica_num);
+
+    let (mut primary_moved, mut replica_moved, mut replica_total) = (0, 0, 0);
+    let mut primaries: HashMap<&str, usize> = HashMap::new();
+    let mut replicas: HashMap<&str, usize> = HashMap::new();
+    for (old_names, new_names) in old.iter().zip(&new) {
+        if old_names.first() != new_names.first() {
+            primary_moved += 1;
+        }
+        replica_moved += new_names.iter().filter(|n| !old_names.contains(n)).count();
+        replica_total += new_names.len();
+        if let Some(name) = new_names.first() {
+            *primaries.entry(name.as_str()).or_default() += 1;
+        }
+        for name in new_names {
+            *replicas.entry(name.as_str()).or_default() += 1;
+        }
+    }
+
+    let (old_shares, new_shares) = (shares(before), shares(after));
+    let loads = |counts: &HashMap<&str, usize>, total: usize| -> Vec<f64> {
+        new_shares
+            .iter()
+            .map(|(name, share)| {
+                let count = counts.get(name).cloned().unwrap_or(0);
+                count as f64 / total as f64 / share
+            })
+            .collect()
+    };
+    let (primary_load_stddev, primary_load_max_min_ratio) =
+        spread(&loads(&primaries, slot_num as usize));
+    let (replica_load_stddev, replica_load_max_min_ratio) =
+        spread(&loads(&replicas, replica_total));
+    let minimum_moved_fraction = new_shares
+        .iter()
+        .map(|(name, share)| (share - old_shares.get(name).cloned().unwrap_or(0.0)).max(0.0))
+        .sum();
+
+    Ok(StrategyReport {
+        strategy: String::from(strategy.name()),
+        primary_load_stddev,
+        primary_load_max_min_ratio,
+        replica_load_stddev,
+        replica_load_max_min_ratio,
+        primary_moved_fraction: primary_moved as f64 / slot_num as f64,
+        replica_moved_fraction: replica_moved as f64 / replica_total.max(1) as f64,
+        minimum_moved_fraction,
+        overflow_fraction: strategy.overflow_fraction(),
+    })
+}
+
+impl fmt::Display for StrategyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:<12} {:>9.4} {:>9.4} {:>9.4} {:>9.4} {:>8.2}% {:>8.2}% {:>8.2}%",
+            self.strategy,
+            self.primary_load_stddev,
+            self.primary_load_max_min_ratio,
+            self.replica_load_stddev,
+            self.replica_load_max_min_ratio,
+            self.primary_moved_fraction * 100.0,
+            self.replica_moved_fraction * 100.0,
+            self.minimum_moved_fraction * 100.0
+        )?;
+        match self.overflow_fraction {
+            Some(fraction) => writeln!(f, " {:>8.2}%", fraction * 100.0),
+            None => writeln!(f, " {:>9}", "-"),
+        }
+    }
+}
+
+impl StrategyReport {
+    /// Column headers matching the `Display` output.
+    pub fn header() -> String {
+        format!(
+            "{:<12} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            "strategy",
+            "p-stddev",
+            "p-max/min",
+            "r-stddev",
+            "r-max/min",
+            "p-moved",
+            "r-moved",
+            "minimum",
+            "overflow"
+        )
+    }
+}
+
+/// Every strategy with its usual parameters, for side-by-side runs;
+/// `epsilon` bounds the loads of the bounded-load ring.
+pub fn all_strategies(
+    vnode_num: u64,
+    slot_num: u64,
+    replica_num: u64,
+    seed: u64,
+    epsilon: f64,
+) -> Result<Vec<Box<dyn PlacementStrategy>>> {
+    Ok(vec![
+        Box::new(VNodeStrategy::new(vnode_num, slot_num, replica_num, seed)),
+        Box::new(RoundRobinStrategy::new()),
+        Box::new(RingStrategy::new(160)),
+        Box::new(BoundedLoadStrategy::new(
+            160,
+            slot_num,
+            replica_num as usize,
+            epsilon,
+        )?),
+        Box::new(JumpStrategy::new()),
+        Box::new(RendezvousStrategy::new()),
+        Box::new(MaglevStrategy::new(65537)?),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::diff_snapshots;
+
+    fn nodes(names: &[&str]) -> Vec<(String, u64)> {
+        names.iter().map(|n| (String::from(*n), 1)).collect()
+    }
+
+    #[test]
+    fn test_place_distinct() {
+        let members = nodes(&["a", "b", "c", "d"]);
+        for mut strategy in all_strategies(1024, 64, 3, 1, 0.25).unwrap() {
+            strategy.build(&members).unwrap();
+            for slot_id in 0..64 {
+                let names = strategy.place(slot_id, 3);
+                let distinct: HashSet<&String> = names.iter().collect();
+                assert_eq!(names.len(), 3, "{}", strategy.name());
+                assert_eq!(distinct.len(), 3, "{}", strategy.name());
+            }
+            // precomputed layouts never place more than their own replica num
+            let most = match strategy.name() {
+                "vnode" | "bounded-load" => 3,
+                _ => 4,
+            };
+            assert_eq!(strategy.place(0, 8).len(), most, "{}", strategy.name());
+        }
+    }
+
+    #[test]
+    fn test_maglev_table_size() {
+        for size in &[0, 1, 4, 65536] {
+            assert!(matches!(
+                MaglevStrategy::new(*size),
+                Err(Error::InvalidStrategy(_))
+            ));
+        }
+        let mut maglev = MaglevStrategy::new(2).unwrap();
+        maglev.build(&nodes(&["a", "b"])).unwrap();
+        assert_eq!(maglev.place(0, 2).len(), 2);
+    }
+
+    #[test]
+    fn test_jump_consistent_hash() {
+        for key in 0..1000u64 {
+            let b4 = jump_consistent_hash(key, 4);
+            let b5 = jump_consistent_hash(key, 5);
+            assert!(b4 < 4);
+            assert!(b5 == b4 || b5 == 4);
+        }
+    }
+
+    #[test]
+    fn test_compare_strategy() {
+        let before = nodes(&["a", "b", "c", "d"]);
+        let after = nodes(&["a", "b", "c", "d", "e"]);
+        for mut strategy in all_strategies(16384, 1024, 3, 1, 0.25).unwrap() {
+            let report = compare_strategy(&mut *strategy, &before, &after, 1024, 3).unwrap();
+            assert!((report.minimum_moved_fraction - 0.2).abs() < 1e-9);
+            assert!(report.primary_moved_fraction >= 0.15, "{:?}", report);
+            if strategy.name() != "round-robin" {
+                assert!(report.primary_moved_fraction < 0.3, "{:?}", report);
+                assert!(report.primary_load_max_min_ratio < 1.5, "{:?}", report);
+            }
+        }
+        let mut rendezvous = RendezvousStrategy::new();
+        let weighted = vec![(String::from("a"), 1), (String::from("b"), 3)];
+        let report = compare_strategy(&mut rendezvous, &weighted, &weighted, 4096, 1).unwrap();
+        assert_eq!(report.primary_moved_fraction, 0.0);
+        assert!(report.primary_load_max_min_ratio < 1.1, "{:?}", report);
+        assert_eq!(report.overflow_fraction, None);
+
+        // a weighted newcomer only takes vnodes, never reshuffles the rest
+        let mut vnode = VNodeStrategy::new(4096, 256, 3, 1);
+        vnode.build(&before).unwrap();
+        let old = vnode.cluster().unwrap().snapshot();
+        let mut after = before.clone();
+        after.push((String::from("e"), 2));
+        vnode.build(&after).unwrap();
+        let new = vnode.cluster().unwrap().snapshot();
+        let moved = diff_snapshots(&old, &new);
+        assert!(moved.len() <= 4096 * 2 / 6);
+        assert!(moved.iter().all(|(_, _, to)| to.as_deref() == Some("e")));
+    }
+
+    #[test]
+    fn test_bounded_load() {
+        let members = nodes(&["a", "b", "c", "d", "e"]);
+        // the busiest node's primaries against the average of 200
+        let max_load = |strategy: &dyn PlacementStrategy| {
+            let mut counts: HashMap<String, usize> = HashMap::new();
+            for slot_id in 0..1000 {
+                let names = strategy.place(slot_id, 3);
+                *counts.entry(names[0].clone()).or_default() += 1;
+            }
+            *counts.values().max().unwrap() as f64 / 200.0
+        };
+
+        let mut ring = RingStrategy::new(4);
+        ring.build(&members).unwrap();
+        let mut bounded = BoundedLoadStrategy::new(4, 1000, 3, 0.1).unwrap();
+        bounded.build(&members).unwrap();
+        assert!(max_load(&ring) > 1.1);
+        assert!(max_load(&bounded) <= 1.1);
+        assert!(bounded.overflow_fraction().unwrap() > 0.0);
+        for slot_id in 0..1000 {
+            let names = bounded.place(slot_id, 3);
+            let distinct: HashSet<&String> = names.iter().collect();
+            assert_eq!(distinct.len(), 3);
+        }
+
+        // with room to spare nothing overflows and the ring is unchanged
+        let mut loose = BoundedLoadStrategy::new(4, 1000, 3, 10.0).unwrap();
+        loose.build(&members).unwrap();
+        assert_eq!(loose.overflow_fraction(), Some(0.0));
+        assert!((0..1000).all(|slot_id| loose.place(slot_id, 3) == ring.place(slot_id, 3)));
+
+        // one heavy tenant still leaves the other slots capped
+        let mut loads = vec![1; 1000];
+        loads[7] = 500;
+        let mut skewed = BoundedLoadStrategy::new(4, 1000, 3, 0.1)
+            .unwrap()
+            .with_slot_loads(loads);
+        skewed.build(&members).unwrap();
+        assert_eq!(skewed.place(7, 3).len(), 3);
+        assert!(skewed.overflow_fraction().unwrap() > bounded.overflow_fraction().unwrap());
+        for epsilon in [0.0, -0.1, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                BoundedLoadStrategy::new(4, 1000, 3, epsilon),
+                Err(Error::InvalidEpsilon(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use crate::cluster::ClusterManager;
+use crate::error::{Error, Result};
+use crate::migration::MigrationPlan;
+use rand::Rng;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Weight given to nodes that were never assigned one.
+pub const DEFAULT_WEIGHT: u64 = 1;
+
+/// A node's vnode count relative to its weight, ordered by `load / weight`
+/// and then by name so heaps pop nodes deterministically.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Fill {
+    pub(crate) load: usize,
+    pub(crate) weight: u64,
+    pub(crate) name: String,
+}
+
+impl Ord for Fill {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.load as u64 * other.weight)
+            .cmp(&(other.load as u64 * self.weight))
+            .then_with(|| self.name.cmp(&other.name))
+    }
+}
+
+impl PartialOrd for Fill {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl ClusterManager {
+    pub(crate) fn total_weight(&self) -> u64 {
+        self.nodes.values().map(|node| node.weight).sum()
+    }
+
+    /// Returns the fraction of vnodes the node `name` should own.
+    pub fn expected_share(&self, name: &str) -> Option<f64> {
+        let node = self.nodes.get(name)?;
+        Some(node.weight as f64 / self.total_weight() as f64)
+    }
+
+    /// Splits `total` vnodes over `names` in proportion to their weights,
+    /// handing the remainder out by largest fractional part.
+    pub(crate) fn weighted_quotas(&self, names: &[String], total: u64) -> Vec<u64> {
+        let weights: Vec<u64> = names.iter().map(|n| self.nodes[n].weight).collect();
+        let weight_sum: u64 = weights.iter().sum();
+        let mut quotas: Vec<u64> = weights.iter().map(|w| total * w / weight_sum).collect();
+        let mut order: Vec<usize> = (0..names.len()).collect();
+        order.sort_by_key(|k| std::cmp::Reverse(total * weights[*k] % weight_sum));
+        let left = total - quotas.iter().sum::<u64>();
+        for k in order.into_iter().take(left as usize) {
+            quotas[k] += 1;
+        }
+        quotas
+    }
+
+    /// Sets the weight of the node `name` and, once vnodes are allocated,
+    /// rebalances so ownership follows the new weights.
+    pub fn set_weight(&mut self, name: &str, weight: u64) -> Result<MigrationPlan> {
+        if weight == 0 {
+            return Err(Error::InvalidWeight(weight));
+        }
+        let node = self
+            .nodes
+            .get_mut(name)
+            .ok_or_else(|| Error::NodeNotFound(String::from(name)))?;
+        node.weight = weight;
+        if self.vnodes.iter().all(|vnode| vnode.node_name.is_none()) {
+            return Ok(MigrationPlan::default());
+        }
+        self.rebalance()
+    }
+
+    /// Moves vnodes from the fullest to the emptiest nodes relative to their
+    /// weights until no single move brings the layout closer to the weights.
+    ///
+    /// Returns the migration plan that was carried out, ordered by vnode id.
+    pub fn rebalance(&mut self) -> Result<MigrationPlan> {
+        if let Some(vnode) = self.vnodes.iter().find(|v| v.node_name.is_none()) {
+            return Err(Error::VNodeNotAllocated(vnode.id));
+        }
+
+        let names: Vec<String> = self.node_names().into_iter().map(String::from).collect();
+        let mut candidates: HashMap<String, Vec<u64>> = HashMap::new();
+        for name in &names {
+            let mut vnode_ids: Vec<u64> = self.nodes[name].vnode_set.iter().cloned().collect();
+            vnode_ids.sort_unstable();
+            self.rng.shuffle(&mut vnode_ids);
+            candidates.insert(name.clone(), vnode_ids);
+        }
+
+        let mut moves = Vec::new();
+        let mut exhausted: Vec<String> = Vec::new();
+        loop {
+            let mut fills: Vec<Fill> = names
+                .iter()
+                .map(|name| Fill {
+                    load: self.nodes[name].vnode_set.len(),
+                    weight: self.nodes[name].weight,
+                    name: name.clone(),
+                })
+                .collect();
+            fills.sort();
+            let donor = match fills.iter().rev().find(|f| !exhausted.contains(&f.name)) {
+                Some(donor) => donor.clone(),
+                None => break,
+            };
+
+            let mut picked = None;
+            for receiver in &fills {
+                // the move must lower sum(load^2 / weight), so this terminates
+                let lhs = (2 * receiver.load as u64 + 1) * donor.weight;
+                let rhs = (2 * donor.load as u64).saturating_sub(1) * receiver.weight;
+                if lhs >= rhs {
+                    break;
+                }
+                if let Some(pos) = candidates[&donor.name]
+                    .iter()
+                    .rposition(|v| self.fits(*v, &receiver.name))
+                {
+                    picked = Some((pos, receiver.name.clone()));
+                    break;
+                }
+            }
+
+            let (pos, name_dst) = match picked {
+                Some(picked) => picked,
+                None => {
+                    exhausted.push(donor.name);
+                    continue;
+                }
+            };
+            let vnode_id = candidates.get_mut(&donor.name).unwrap().swap_remove(pos);
+            if let Some(node_src) = self.nodes.get_mut(&donor.name) {
+                node_src.drop_vnode(vnode_id);
+            }
+            if let Some(node_dst) = self.nodes.get_mut(&name_dst) {
+                node_dst.pickup_vnode(vnode_id);
+            }
+            self.vnodes[vnode_id as usize].assign(Some(name_dst.clone()));
+            moves.push(self.migration_step(vnode_id, &donor.name, &name_dst));
+        }
+
+        moves.sort_by_key(|m| m.vnode_id);
+        Ok(MigrationPlan {
+            steps: moves,
+            ..MigrationPlan::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_cluster(weights: &[(&str, u64)]) -> ClusterManager {
+        let mut cm = ClusterManager::with_seed(11);
+        cm.init_vnodes(4096).unwrap();
+        cm.init_slots(64).unwrap();
+        for (name, weight) in weights {
+            cm.add_node(name).unwrap();
+            cm.set_weight(name, *weight).unwrap();
+        }
+        cm.allocate(&[]).unwrap();
+        cm
+    }
+
+    fn assert_proportional(cm: &ClusterManager, slack: f64) {
+        for node in cm.nodes() {
+            let share = node.vnode_set().len() as f64 / cm.vnode_num() as f64;
+            let expected = cm.expected_share(node.name()).unwrap();
+            assert!(
+                (share - expected).abs() <= slack,
+                "node {} share {} expected {}",
+                node.name(),
+                share,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_weighted_allocate() {
+        let cm = new_cluster(&[("a", 1), ("b", 2), ("c", 3), ("d", 2)]);
+        assert_eq!(cm.node("c").unwrap().weight(), 3);
+        assert_eq!(cm.expected_share("c"), Some(3.0 / 8.0));
+        assert_proportional(&cm, 0.002);
+        assert!(cm.validate_placement().is_empty());
+    }
+
+    #[test]
+    fn test_weighted_scale() {
+        let mut cm = new_cluster(&[("a", 1), ("b", 1), ("c", 2)]);
+        let plan = cm.scale("d").unwrap();
+        assert_eq!(plan.len(), 4096 / 5);
+        assert_proportional(&cm, 0.002);
+        cm.decommission("a").unwrap();
+        assert_proportional(&cm, 0.002);
+        assert!(cm.validate_placement().is_empty());
+    }
+
+    #[test]
+    fn test_scale_weighted() {
+        let mut cm = new_cluster(&[("a", 1), ("b", 1), ("c", 2)]);
+        assert_eq!(cm.scale_weighted("d", 0), Err(Error::InvalidWeight(0)));
+        let mut rebalanced = cm.clone();
+        let plan = cm.scale_weighted("d", 4).unwrap();
+        assert_eq!(plan.len(), 4096 * 4 / 8);
+        assert!(plan.steps.iter().all(|m| m.to == "d"));
+        assert_eq!(cm.node("d").unwrap().weight(), 4);
+        assert_proportional(&cm, 0.002);
+
+        rebalanced.add_node("d").unwrap();
+        let plan_rebalanced = rebalanced.set_weight("d", 4).unwrap();
+        assert!(plan.len() <= plan_rebalanced.len());
+    }
+
+    #[test]
+    fn test_set_weight() {
+        let mut cm = new_cluster(&[("a", 1), ("b", 1), ("c", 1), ("d", 1)]);
+        assert_eq!(cm.set_weight("a", 0), Err(Error::InvalidWeight(0)));
+        assert_eq!(
+            cm.set_weight("x", 1),
+            Err(Error::NodeNotFound(String::from("x")))
+        );
+
+        let plan = cm.set_weight("a", 4).unwrap();
+        assert!(plan.steps.iter().all(|m| m.to == "a"));
+        assert_proportional(&cm, 0.002);
+
+        let plan = cm.set_weight("a", 1).unwrap();
+        assert!(plan.steps.iter().all(|m| m.from == "a"));
+        assert_proportional(&cm, 0.002);
+        assert!(cm.validate_placement().is_empty());
+        assert!(cm.rebalance().unwrap().is_empty());
+    }
+}